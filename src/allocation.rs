@@ -1,68 +1,227 @@
+use std::convert::TryFrom;
+use std::mem;
 
-#[derive(Eq, PartialEq, Copy, Clone, Debug)]
+use crate::bitset::GenerationalIndexBitSet;
+
+// largest index and generation a GenerationalIndex can represent; the
+// packed representation fits both into a single u64
+#[cfg(not(feature = "packed_index"))]
+const MAX_INDEX: usize = usize::MAX;
+#[cfg(not(feature = "packed_index"))]
+const MAX_GENERATION: u64 = u64::MAX;
+
+#[cfg(feature = "packed_index")]
+const INDEX_BITS: u32 = 24;
+#[cfg(feature = "packed_index")]
+const MAX_INDEX: usize = (1 << INDEX_BITS) - 1;
+#[cfg(feature = "packed_index")]
+const MAX_GENERATION: u64 = (1 << 40) - 1;
+
+#[cfg(not(feature = "packed_index"))]
+#[derive(Eq, PartialEq, Hash, Copy, Clone, Debug)]
 pub struct GenerationalIndex {
     index: usize,
     generation: u64,
 }
 
+// generation in the upper 40 bits, index in the lower 24 bits
+#[cfg(feature = "packed_index")]
+#[derive(Eq, PartialEq, Hash, Copy, Clone, Debug)]
+pub struct GenerationalIndex {
+    bits: u64,
+}
+
 impl GenerationalIndex {
+    #[cfg(not(feature = "packed_index"))]
+    pub(crate) fn new(index: usize, generation: u64) -> GenerationalIndex {
+        GenerationalIndex {
+            index,
+            generation
+        }
+    }
+
+    #[cfg(feature = "packed_index")]
+    pub(crate) fn new(index: usize, generation: u64) -> GenerationalIndex {
+        debug_assert!(index <= MAX_INDEX && generation <= MAX_GENERATION);
+        GenerationalIndex {
+            bits: (generation << INDEX_BITS) | index as u64
+        }
+    }
+
+    /// Sentinel for "no entity", for handles stored without an `Option`.
+    /// Allocators never hand it out and never consider it live, as its
+    /// index is the one slot an allocator never allocates.
+    pub fn null() -> GenerationalIndex {
+        GenerationalIndex::new(MAX_INDEX, MAX_GENERATION)
+    }
+
+    pub fn is_null(&self) -> bool {
+        *self == GenerationalIndex::null()
+    }
+
+    #[cfg(not(feature = "packed_index"))]
     pub fn index(&self) -> usize {
-        return self.index;
+        self.index
+    }
+
+    #[cfg(feature = "packed_index")]
+    pub fn index(&self) -> usize {
+        (self.bits & MAX_INDEX as u64) as usize
+    }
+
+    #[cfg(not(feature = "packed_index"))]
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    #[cfg(feature = "packed_index")]
+    pub fn generation(&self) -> u64 {
+        self.bits >> INDEX_BITS
     }
 }
 
+impl GenerationalIndex {
+    /// Encodes the index into a `u64` for network or binary formats, with
+    /// the generation in the upper and the index in the lower 32 bits.
+    /// Returns `None` if the index or generation does not fit into 32 bits.
+    /// This includes the null index, and slots that have been reused more
+    /// than `u32::MAX` times.
+    pub fn to_bits(&self) -> Option<u64> {
+        let index = u32::try_from(self.index()).ok()?;
+        let generation = u32::try_from(self.generation()).ok()?;
+        Some((u64::from(generation) << 32) | u64::from(index))
+    }
+
+    /// Decodes a value produced by `to_bits`. Returns `None` if the values
+    /// cannot be represented on this platform or configuration.
+    pub fn from_bits(bits: u64) -> Option<GenerationalIndex> {
+        let index = usize::try_from(bits & u64::from(u32::MAX)).ok()?;
+        // 32 bit generations always fit, the packed index only has 24 bits
+        #[cfg(feature = "packed_index")]
+        {
+            if index >= MAX_INDEX {
+                return None;
+            }
+        }
+        Some(GenerationalIndex::new(index, bits >> 32))
+    }
+}
+
+/// Orders by raw index first and generation second. The order carries no
+/// ECS meaning, it only gives sorted containers a canonical key.
+impl Ord for GenerationalIndex {
+    fn cmp(&self, other: &GenerationalIndex) -> std::cmp::Ordering {
+        (self.index(), self.generation()).cmp(&(other.index(), other.generation()))
+    }
+}
+
+impl PartialOrd for GenerationalIndex {
+    fn partial_cmp(&self, other: &GenerationalIndex) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+#[derive(Clone)]
 struct AllocatorEntry {
     is_live: bool,
     generation: u64,
 }
 
+#[derive(Clone)]
 pub struct GenerationalIndexAllocator {
     entries: Vec<AllocatorEntry>,
     free: Vec<usize>,
+    live: usize,
 }
 
 impl GenerationalIndexAllocator {
     pub fn new() -> GenerationalIndexAllocator {
         GenerationalIndexAllocator {
             entries: Vec::new(),
-            free: Vec::new()
+            free: Vec::new(),
+            live: 0
+        }
+    }
+
+    /// Creates an allocator with room for `capacity` indices before its
+    /// internal storage has to grow. The capacity is only a hint, more
+    /// indices can still be allocated afterwards.
+    pub fn with_capacity(capacity: usize) -> GenerationalIndexAllocator {
+        GenerationalIndexAllocator {
+            entries: Vec::with_capacity(capacity),
+            free: Vec::with_capacity(capacity),
+            live: 0
         }
     }
 
     pub fn allocate(&mut self) -> GenerationalIndex {
         // check if we can reuse and unused entry
-        if !self.free.is_empty() {
-            // we unwrap as we just checked for content
-            let potential_index = self.free.pop().unwrap();
-
-            // check if the index is actually free, else go to new allocation
-            if let Some(allocator_entry) = self.entries.get_mut(potential_index) {
-                if !allocator_entry.is_live {
-                    // adjust allocator entry
-                    allocator_entry.is_live = true;
-                    allocator_entry.generation += 1;
-
-                    return GenerationalIndex {
-                        index: potential_index,
-                        generation: allocator_entry.generation
-                    };
-                }
-            }
+        if let Some(index) = self.reuse_free_entry() {
+            return index;
         }
 
         // allocate a completly new index
         let index = self.entries.len();
         let generation = 0;
+        assert!(index < MAX_INDEX, "GenerationalIndexAllocator ran out of indices");
 
         self.entries.push(AllocatorEntry{
             is_live: true,
-            generation: generation
+            generation
         });
+        self.live += 1;
 
-        return GenerationalIndex {
-            index,
-            generation
-        };
+        GenerationalIndex::new(index, generation)
+    }
+
+    /// Allocates `count` indices at once. Free slots are reused first, the
+    /// remainder is appended with a single reservation.
+    pub fn allocate_many(&mut self, count: usize) -> Vec<GenerationalIndex> {
+        let mut indices = Vec::with_capacity(count);
+        while indices.len() < count {
+            match self.reuse_free_entry() {
+                Some(index) => indices.push(index),
+                None => break
+            }
+        }
+
+        let remaining = count - indices.len();
+        let start = self.entries.len();
+        assert!(remaining < MAX_INDEX - start, "GenerationalIndexAllocator ran out of indices");
+        self.entries.reserve(remaining);
+        for index in start..start + remaining {
+            self.entries.push(AllocatorEntry {
+                is_live: true,
+                generation: 0
+            });
+            indices.push(GenerationalIndex::new(index, 0));
+        }
+        self.live += remaining;
+
+        indices
+    }
+
+    fn reuse_free_entry(&mut self) -> Option<GenerationalIndex> {
+        while let Some(potential_index) = self.free.pop() {
+            // check if the index is actually free, else try the next one
+            if let Some(allocator_entry) = self.entries.get_mut(potential_index) {
+                if !allocator_entry.is_live {
+                    // a wrapped generation would revive stale indices, so an
+                    // exhausted slot is retired and stays dead for good
+                    if allocator_entry.generation == MAX_GENERATION {
+                        continue;
+                    }
+
+                    // adjust allocator entry
+                    allocator_entry.is_live = true;
+                    allocator_entry.generation += 1;
+                    self.live += 1;
+
+                    return Some(GenerationalIndex::new(potential_index, allocator_entry.generation));
+                }
+            }
+        }
+        None
     }
 
     pub fn deallocate(&mut self, index: GenerationalIndex) -> bool {
@@ -74,76 +233,183 @@ impl GenerationalIndexAllocator {
                     return false;
                 }
 
-                if allocator_entry.generation != index.generation {
+                if allocator_entry.generation != index.generation() {
                     return false;
                 }
 
                 allocator_entry.is_live = false;
                 self.free.push(index.index());
-                return true;
+                self.live -= 1;
+                true
             },
-            None => {
-                return false;
-            }
+            None => false
         }
     }
 
+    /// Deallocates all live indices of the slice and returns how many were
+    /// deallocated. Dead and stale indices are skipped.
+    pub fn deallocate_batch(&mut self, indices: &[GenerationalIndex]) -> usize {
+        let entries = &mut self.entries;
+        let freed = indices.iter().filter_map(|index| {
+            let allocator_entry = entries.get_mut(index.index())?;
+            if allocator_entry.is_live && allocator_entry.generation == index.generation() {
+                allocator_entry.is_live = false;
+                Some(index.index())
+            } else {
+                None
+            }
+        });
+
+        let before = self.free.len();
+        self.free.extend(freed);
+        let count = self.free.len() - before;
+        self.live -= count;
+        count
+    }
+
     pub fn is_live(&self, index: GenerationalIndex) -> bool {
         if let Some(allocator_entry) = self.entries.get(index.index()) {
-            allocator_entry.generation == index.generation && allocator_entry.is_live
+            allocator_entry.generation == index.generation() && allocator_entry.is_live
         } else {
             false
         }
     }
 
-    pub fn live_indices(&self) -> Vec<GenerationalIndex> {
-        let mut live = Vec::new();
-        for (index, entry) in self.entries.iter().enumerate() {
-            if entry.is_live {
-                live.push(GenerationalIndex {
-                    index: index,
-                    generation: entry.generation
-                })
+    /// Returns the allocator to the state of `new()`. Every previously
+    /// issued index is dead afterwards. As generations restart at 0, old
+    /// indices may alias freshly allocated ones, so they should be dropped.
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.free.clear();
+        self.live = 0;
+    }
+
+    /// Number of indices that are currently live.
+    pub fn live_count(&self) -> usize {
+        self.live
+    }
+
+    /// Number of slots that have ever been allocated, live or not.
+    pub fn total_count(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Share of slots that are dead, between 0 for none and 1 for all;
+    /// 0 if no slot was ever allocated. See `defragment`.
+    pub fn fragmentation_ratio(&self) -> f32 {
+        if self.entries.is_empty() {
+            return 0.0;
+        }
+        1.0 - self.live as f32 / self.entries.len() as f32
+    }
+
+    /// Moves live indices from the back into dead slots at the front, so
+    /// live indices occupy the lowest slots. Returns the old and the new
+    /// index of every moved index; all other indices stay as they are. The
+    /// old indices are dead afterwards. This walks all slots, so it is
+    /// meant for rare occasions such as saving.
+    pub fn defragment(&mut self) -> Vec<(GenerationalIndex, GenerationalIndex)> {
+        let mut moved = Vec::new();
+        let mut front = 0;
+        let mut back = self.entries.len();
+        loop {
+            // the lowest dead slot that can still be reused
+            while front < back && (self.entries[front].is_live || self.entries[front].generation == MAX_GENERATION) {
+                front += 1;
             }
+            // the highest live slot
+            while back > front && !self.entries[back - 1].is_live {
+                back -= 1;
+            }
+            if back <= front + 1 {
+                break;
+            }
+            back -= 1;
+
+            let old = GenerationalIndex::new(back, self.entries[back].generation);
+            self.entries[back].is_live = false;
+            // the slot is reused as if allocated again, so indices from its
+            // previous use stay dead
+            let target = &mut self.entries[front];
+            target.is_live = true;
+            target.generation += 1;
+            moved.push((old, GenerationalIndex::new(front, target.generation)));
         }
-        live
+
+        // reuse the lowest free slots first
+        self.free = (0..self.entries.len())
+            .rev()
+            .filter(|slot| !self.entries[*slot].is_live)
+            .collect();
+        moved
+    }
+
+    /// Lazily iterates over all live indices in ascending slot order.
+    pub fn live_indices(&self) -> impl Iterator<Item = GenerationalIndex> + '_ {
+        self.entries.iter()
+            .enumerate()
+            .filter(|(_, entry)| entry.is_live)
+            .map(|(index, entry)| GenerationalIndex::new(index, entry.generation))
     }
 }
 
+impl Default for GenerationalIndexAllocator {
+    fn default() -> GenerationalIndexAllocator {
+        GenerationalIndexAllocator::new()
+    }
+}
+
+#[derive(Clone)]
 struct ArrayEntry<T> {
     value: T,
     generation: u64,
+    // set whenever the value may have been changed, see `is_dirty`
+    dirty: bool,
 }
 
-pub struct GenerationalIndexArray<T>(Vec<Option<ArrayEntry<T>>>);
+#[derive(Clone)]
+pub struct GenerationalIndexArray<T> {
+    slots: Vec<Option<ArrayEntry<T>>>,
+    // mirrors which slots are Some, for fast joins between arrays
+    occupied: GenerationalIndexBitSet,
+    // number of Some slots, so that len does not need to count bits
+    len: usize,
+}
 
 impl<T> GenerationalIndexArray<T> {
     pub fn new() -> GenerationalIndexArray<T> {
         GenerationalIndexArray {
-            0: Vec::new()
+            slots: Vec::new(),
+            occupied: GenerationalIndexBitSet::new(),
+            len: 0
         }
     }
 
     pub fn set(&mut self, index: GenerationalIndex, value: T) {
         let inx = index.index();
         // extend vector if too short
-        while self.0.len() <= inx + 1 {
-            self.0.push(None);
+        if self.slots.len() <= inx {
+            self.slots.resize_with(inx + 1, || None);
         }
-        self.0[inx] = Some(ArrayEntry {
+        let previous = self.slots[inx].replace(ArrayEntry {
             value,
-            generation: index.generation
+            generation: index.generation(),
+            dirty: true
         });
+        if previous.is_none() {
+            self.len += 1;
+        }
+        self.occupied.set(inx);
     }
 
     pub fn get(&self, index: GenerationalIndex) -> Option<&T> {
-        if self.0.len() <= index.index {
+        if self.slots.len() <= index.index() {
             return None;
         }
-        match &self.0[index.index()] {
+        match &self.slots[index.index()] {
             None => None,
             Some(entry) => {
-                if index.generation == entry.generation {
+                if index.generation() == entry.generation {
                     Some(&entry.value)
                 } else {
                     None
@@ -153,13 +419,14 @@ impl<T> GenerationalIndexArray<T> {
     }
 
     pub fn get_mut(&mut self, index: GenerationalIndex) -> Option<&mut T> {
-        if self.0.len() <= index.index() {
+        if self.slots.len() <= index.index() {
             return None;
         }
-        match &mut self.0[index.index()] {
+        match &mut self.slots[index.index()] {
             None => None,
             Some(entry) => {
-                if index.generation == entry.generation {
+                if index.generation() == entry.generation {
+                    entry.dirty = true;
                     Some(&mut entry.value)
                 } else {
                     None
@@ -168,24 +435,258 @@ impl<T> GenerationalIndexArray<T> {
         }
     }
 
+    /// Iterates over all occupied slots together with their index.
+    pub fn iter(&self) -> Iter<'_, T> {
+        Iter {
+            slots: self.slots.iter().enumerate()
+        }
+    }
+
+    /// Mutable counterpart of `iter`. Marks every visited value as dirty.
+    pub fn iter_mut(&mut self) -> IterMut<'_, T> {
+        IterMut {
+            slots: self.slots.iter_mut().enumerate()
+        }
+    }
+
+    /// Number of occupied slots.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Number of slots, occupied or not.
+    pub fn slot_count(&self) -> usize {
+        self.slots.len()
+    }
+
+    /// Number of slots the array can hold without reallocating.
+    pub fn capacity(&self) -> usize {
+        self.slots.capacity()
+    }
+
+    /// Reserves room for at least `additional` slots beyond the current
+    /// `slot_count`, e.g. before setting values for freshly allocated
+    /// indices.
+    pub fn reserve(&mut self, additional: usize) {
+        self.slots.reserve(additional);
+    }
+
+    /// Drops the empty slots at the end and releases unused memory.
+    /// Indices stay valid.
+    pub fn shrink_to_fit(&mut self) {
+        let used = self.slots.iter().rposition(Option::is_some).map_or(0, |last| last + 1);
+        self.slots.truncate(used);
+        self.slots.shrink_to_fit();
+        self.occupied.shrink_to_fit();
+    }
+
+    /// The positions of all occupied slots.
+    pub fn occupied(&self) -> &GenerationalIndexBitSet {
+        &self.occupied
+    }
+
+    // mutable access to single slots in ascending order, for joins that
+    // must not mark the values they skip as dirty
+    pub(crate) fn slots_mut(&mut self) -> SlotsMut<'_, T> {
+        SlotsMut {
+            slots: &mut self.slots,
+            offset: 0
+        }
+    }
+
+    /// Looks up a slot by its raw position, regardless of generation.
+    pub fn get_slot(&self, slot: usize) -> Option<(GenerationalIndex, &T)> {
+        let entry = self.slots.get(slot)?.as_ref()?;
+        Some((GenerationalIndex::new(slot, entry.generation), &entry.value))
+    }
+
+    /// Whether the value was set or mutably borrowed since the last
+    /// `mark_clean`.
+    pub fn is_dirty(&self, index: GenerationalIndex) -> bool {
+        match self.slots.get(index.index()) {
+            Some(Some(entry)) => entry.generation == index.generation() && entry.dirty,
+            _ => false
+        }
+    }
+
+    pub fn mark_clean(&mut self, index: GenerationalIndex) {
+        if let Some(Some(entry)) = self.slots.get_mut(index.index()) {
+            if entry.generation == index.generation() {
+                entry.dirty = false;
+            }
+        }
+    }
+
+    pub fn mark_all_clean(&mut self) {
+        for entry in self.slots.iter_mut().flatten() {
+            entry.dirty = false;
+        }
+    }
+
+    /// Checks for a value at the index without borrowing it.
+    pub fn contains(&self, index: GenerationalIndex) -> bool {
+        match self.slots.get(index.index()) {
+            Some(Some(entry)) => entry.generation == index.generation(),
+            _ => false
+        }
+    }
+
+    /// Mutable access to the values at several indices at once, in the
+    /// order of `indices`. Returns `None` if any index has no value or if
+    /// an index appears twice. Marks the values as dirty.
+    pub fn get_many_mut(&mut self, indices: &[GenerationalIndex]) -> Option<Vec<&mut T>> {
+        if !indices.iter().all(|index| self.contains(*index)) {
+            return None;
+        }
+        // positions into `indices`, ordered by slot
+        let mut order: Vec<usize> = (0..indices.len()).collect();
+        order.sort_unstable_by_key(|position| indices[*position].index());
+        if order.windows(2).any(|pair| indices[pair[0]].index() == indices[pair[1]].index()) {
+            return None;
+        }
+
+        // split off one slot after the other, so every borrow is disjoint
+        let mut values: Vec<Option<&mut T>> = indices.iter().map(|_| None).collect();
+        let mut rest = self.slots.as_mut_slice();
+        let mut offset = 0;
+        for position in order {
+            let slot = indices[position].index();
+            let (head, tail) = mem::take(&mut rest).split_at_mut(slot - offset + 1);
+            rest = tail;
+            offset = slot + 1;
+            // checked by `contains` above
+            let entry = head.last_mut().unwrap().as_mut().unwrap();
+            entry.dirty = true;
+            values[position] = Some(&mut entry.value);
+        }
+        values.into_iter().collect()
+    }
+
+    /// Takes the value out of the slot if the generation matches.
+    pub fn remove(&mut self, index: GenerationalIndex) -> Option<T> {
+        let slot = self.slots.get_mut(index.index())?;
+        match slot {
+            Some(entry) if entry.generation == index.generation() => {
+                self.occupied.clear(index.index());
+                self.len -= 1;
+                slot.take().map(|entry| entry.value)
+            },
+            _ => None
+        }
+    }
+
+    /// Removes all values.
+    pub fn clear(&mut self) {
+        self.slots.clear();
+        self.occupied = GenerationalIndexBitSet::new();
+        self.len = 0;
+    }
+
     pub fn delete(&mut self, index: GenerationalIndex) {
-        if self.0.len() <= index.index() {
+        if self.slots.len() <= index.index() {
             return;
         }
 
-        if let Some(entry) = &mut self.0[index.index()] {
-            if entry.generation != index.generation {
+        if let Some(entry) = &mut self.slots[index.index()] {
+            if entry.generation != index.generation() {
                 return;
             }
         }
-        self.0[index.index()] = None;
+        if self.slots[index.index()].take().is_some() {
+            self.len -= 1;
+        }
+        self.occupied.clear(index.index());
+    }
+}
+
+pub struct Iter<'a, T> {
+    slots: std::iter::Enumerate<std::slice::Iter<'a, Option<ArrayEntry<T>>>>,
+}
+
+impl<'a, T> Iterator for Iter<'a, T> {
+    type Item = (GenerationalIndex, &'a T);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        for (index, slot) in &mut self.slots {
+            if let Some(entry) = slot {
+                let generation = entry.generation;
+                return Some((GenerationalIndex::new(index, generation), &entry.value));
+            }
+        }
+        None
+    }
+}
+
+pub struct IterMut<'a, T> {
+    slots: std::iter::Enumerate<std::slice::IterMut<'a, Option<ArrayEntry<T>>>>,
+}
+
+impl<'a, T> Iterator for IterMut<'a, T> {
+    type Item = (GenerationalIndex, &'a mut T);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        for (index, slot) in &mut self.slots {
+            // destructure so the generation can be copied while the value
+            // is borrowed mutably
+            if let Some(ArrayEntry { value, generation, dirty }) = slot {
+                *dirty = true;
+                return Some((GenerationalIndex::new(index, *generation), value));
+            }
+        }
+        None
+    }
+}
+
+// a value handed out by SlotsMut together with its dirty flag
+pub(crate) type SlotMut<'a, T> = (GenerationalIndex, &'a mut T, &'a mut bool);
+
+pub(crate) struct SlotsMut<'a, T> {
+    // the slots from position `offset` on; earlier ones were handed out
+    // or skipped already
+    slots: &'a mut [Option<ArrayEntry<T>>],
+    offset: usize,
+}
+
+impl<'a, T> SlotsMut<'a, T> {
+    // the value at `slot`, skipping all slots before it; slots have to be
+    // requested in ascending order. Setting the dirty flag is up to the
+    // caller.
+    pub(crate) fn take(&mut self, slot: usize) -> Option<SlotMut<'a, T>> {
+        let skip = slot.checked_sub(self.offset)?;
+        let slots = mem::take(&mut self.slots);
+        if skip >= slots.len() {
+            return None;
+        }
+        let (entry, rest) = slots[skip..].split_first_mut()?;
+        self.slots = rest;
+        self.offset = slot + 1;
+        let ArrayEntry { value, generation, dirty } = entry.as_mut()?;
+        Some((GenerationalIndex::new(slot, *generation), value, dirty))
     }
 }
 
+impl<T> Default for GenerationalIndexArray<T> {
+    fn default() -> GenerationalIndexArray<T> {
+        GenerationalIndexArray::new()
+    }
+}
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::collections::HashMap;
+    use std::collections::hash_map::DefaultHasher;
+    use std::hash::{Hash, Hasher};
+
+    fn hash_of(index: &GenerationalIndex) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        index.hash(&mut hasher);
+        hasher.finish()
+    }
 
     #[test]
     fn test_add_three_entries() {
@@ -198,9 +699,9 @@ mod tests {
         assert_eq!(index_2.index(), 1);
         assert_eq!(index_3.index(), 2);
 
-        assert_eq!(index_1.generation, 0);
-        assert_eq!(index_2.generation, 0);
-        assert_eq!(index_3.generation, 0);
+        assert_eq!(index_1.generation(), 0);
+        assert_eq!(index_2.generation(), 0);
+        assert_eq!(index_3.generation(), 0);
 
         assert_eq!(allocator.entries.len(), 3);
         for allocator_entry in allocator.entries {
@@ -218,7 +719,7 @@ mod tests {
         assert_ne!(index, new_index);
         assert_eq!(index.index(), new_index.index());
 
-        assert_eq!(new_index.generation, 1);
+        assert_eq!(new_index.generation(), 1);
     }
 
     #[test]
@@ -245,8 +746,627 @@ mod tests {
 
         for i in 0..max_generations {
             let index = allocator.allocate();
-            assert_eq!(index.generation, i);
+            assert_eq!(index.generation(), i);
             assert!(allocator.deallocate(index));
         }
     }
+
+    #[test]
+    fn test_index_as_hash_map_key() {
+        let mut allocator = GenerationalIndexAllocator::new();
+        let index_1 = allocator.allocate();
+        let index_2 = allocator.allocate();
+        let index_3 = allocator.allocate();
+
+        let mut names = HashMap::new();
+        names.insert(index_1, "first");
+        names.insert(index_2, "second");
+        names.insert(index_3, "third");
+
+        assert!(allocator.deallocate(index_2));
+        let reused_index = allocator.allocate();
+        assert_eq!(reused_index.index(), index_2.index());
+
+        names.insert(reused_index, "reused");
+        assert_eq!(names.len(), 4);
+        assert_eq!(names[&index_2], "second");
+        assert_eq!(names[&reused_index], "reused");
+    }
+
+    #[test]
+    fn test_with_capacity() {
+        let mut allocator = GenerationalIndexAllocator::with_capacity(16);
+        assert!(allocator.entries.capacity() >= 16);
+        assert!(allocator.free.capacity() >= 16);
+
+        for i in 0..32 {
+            assert_eq!(allocator.allocate().index(), i);
+        }
+        assert_eq!(allocator.entries.len(), 32);
+    }
+
+    #[test]
+    fn test_live_and_total_count() {
+        let mut allocator = GenerationalIndexAllocator::new();
+        assert_eq!(allocator.live_count(), 0);
+        assert_eq!(allocator.total_count(), 0);
+
+        let index_1 = allocator.allocate();
+        let index_2 = allocator.allocate();
+        let index_3 = allocator.allocate();
+        assert_eq!(allocator.live_count(), 3);
+        assert_eq!(allocator.total_count(), 3);
+
+        assert!(allocator.deallocate(index_2));
+        assert_eq!(allocator.live_count(), 2);
+        assert_eq!(allocator.total_count(), 3);
+
+        // double deallocate is a no-op
+        assert!(!allocator.deallocate(index_2));
+        assert_eq!(allocator.live_count(), 2);
+
+        let _index_4 = allocator.allocate();
+        assert_eq!(allocator.live_count(), 3);
+        assert_eq!(allocator.total_count(), 3);
+
+        assert!(allocator.deallocate(index_1));
+        assert!(allocator.deallocate(index_3));
+        assert_eq!(allocator.live_count(), 1);
+        assert_eq!(allocator.total_count(), 3);
+    }
+
+    #[test]
+    fn test_live_indices() {
+        let mut allocator = GenerationalIndexAllocator::new();
+        assert_eq!(allocator.live_indices().count(), 0);
+
+        let index_1 = allocator.allocate();
+        let index_2 = allocator.allocate();
+        let index_3 = allocator.allocate();
+
+        // push the middle slot through a few generations
+        assert!(allocator.deallocate(index_2));
+        let index_2 = allocator.allocate();
+        assert!(allocator.deallocate(index_2));
+        let index_2 = allocator.allocate();
+        assert_eq!(index_2.generation(), 2);
+
+        assert!(allocator.deallocate(index_1));
+
+        let live: Vec<GenerationalIndex> = allocator.live_indices().collect();
+        assert_eq!(live, vec![index_2, index_3]);
+        for index in live {
+            assert!(allocator.is_live(index));
+        }
+    }
+
+    #[test]
+    fn test_clear() {
+        let mut allocator = GenerationalIndexAllocator::new();
+        let indices: Vec<GenerationalIndex> = (0..10).map(|_| allocator.allocate()).collect();
+        assert!(allocator.deallocate(indices[3]));
+        let reused = allocator.allocate();
+        assert_eq!(reused.generation(), 1);
+
+        allocator.clear();
+        assert_eq!(allocator.live_count(), 0);
+        assert_eq!(allocator.total_count(), 0);
+        assert!(!allocator.is_live(reused));
+        for index in indices {
+            assert!(!allocator.is_live(index));
+        }
+
+        for i in 0..10 {
+            let index = allocator.allocate();
+            assert_eq!(index.index(), i);
+            assert_eq!(index.generation(), 0);
+        }
+    }
+
+    #[test]
+    fn test_array_remove() {
+        let mut allocator = GenerationalIndexAllocator::new();
+        let mut array = GenerationalIndexArray::new();
+        let index_1 = allocator.allocate();
+        let index_2 = allocator.allocate();
+        array.set(index_1, "one");
+
+        // empty slot
+        assert_eq!(array.remove(index_2), None);
+
+        // stale generation
+        let stale = GenerationalIndex::new(index_1.index(), index_1.generation() + 1);
+        assert_eq!(array.remove(stale), None);
+        assert_eq!(array.get(index_1), Some(&"one"));
+
+        assert_eq!(array.remove(index_1), Some("one"));
+        assert_eq!(array.get(index_1), None);
+        assert_eq!(array.remove(index_1), None);
+
+        // out of bounds
+        let far = GenerationalIndex::new(100, 0);
+        assert_eq!(array.remove(far), None);
+    }
+
+    #[test]
+    fn test_array_contains() {
+        let mut allocator = GenerationalIndexAllocator::new();
+        let mut array = GenerationalIndexArray::new();
+        let index_1 = allocator.allocate();
+        let index_2 = allocator.allocate();
+        array.set(index_2, 2);
+
+        // present slot
+        assert!(array.contains(index_2));
+
+        // missing slot
+        assert!(!array.contains(index_1));
+
+        // stale generation
+        let stale = GenerationalIndex::new(index_2.index(), index_2.generation() + 1);
+        assert!(!array.contains(stale));
+
+        // out of bounds
+        let far = GenerationalIndex::new(100, 0);
+        assert!(!array.contains(far));
+    }
+
+    #[test]
+    fn test_array_iter() {
+        let mut allocator = GenerationalIndexAllocator::new();
+        let mut array = GenerationalIndexArray::new();
+        let index_1 = allocator.allocate();
+        let index_2 = allocator.allocate();
+        let index_3 = allocator.allocate();
+        array.set(index_1, 1);
+        array.set(index_3, 3);
+
+        // reuse the empty middle slot with a newer generation
+        assert!(allocator.deallocate(index_2));
+        let index_2 = allocator.allocate();
+        array.set(index_2, 2);
+
+        let entries: Vec<(GenerationalIndex, &i32)> = array.iter().collect();
+        assert_eq!(entries, vec![(index_1, &1), (index_2, &2), (index_3, &3)]);
+        for (index, value) in array.iter() {
+            assert_eq!(array.get(index), Some(value));
+        }
+    }
+
+    #[test]
+    fn test_array_iter_mut() {
+        let mut allocator = GenerationalIndexAllocator::new();
+        let mut array = GenerationalIndexArray::new();
+        let index_1 = allocator.allocate();
+        let _index_2 = allocator.allocate();
+        let index_3 = allocator.allocate();
+        array.set(index_1, 1);
+        array.set(index_3, 3);
+
+        for (index, value) in array.iter_mut() {
+            *value *= 10;
+            assert!(index == index_1 || index == index_3);
+        }
+
+        assert_eq!(array.get(index_1), Some(&10));
+        assert_eq!(array.get(index_3), Some(&30));
+        assert_eq!(array.iter_mut().count(), 2);
+    }
+
+    #[test]
+    fn test_default() {
+        let mut from_default = GenerationalIndexAllocator::default();
+        let mut from_new = GenerationalIndexAllocator::new();
+        assert_eq!(from_default.live_count(), from_new.live_count());
+        assert_eq!(from_default.total_count(), from_new.total_count());
+
+        for _ in 0..3 {
+            assert_eq!(from_default.allocate(), from_new.allocate());
+        }
+        let index = from_default.allocate();
+        assert_eq!(index, from_new.allocate());
+        assert_eq!(from_default.deallocate(index), from_new.deallocate(index));
+        assert_eq!(from_default.allocate(), from_new.allocate());
+
+        let array: GenerationalIndexArray<u32> = GenerationalIndexArray::default();
+        assert_eq!(array.iter().count(), 0);
+    }
+
+    #[test]
+    fn test_allocate_many() {
+        let mut allocator = GenerationalIndexAllocator::new();
+        let first = allocator.allocate_many(5);
+        assert_eq!(first.len(), 5);
+        assert_eq!(allocator.live_count(), 5);
+
+        assert!(allocator.deallocate(first[1]));
+        assert!(allocator.deallocate(first[3]));
+
+        let second = allocator.allocate_many(4);
+        assert_eq!(second.len(), 4);
+        assert_eq!(allocator.live_count(), 7);
+        assert_eq!(allocator.total_count(), 7);
+
+        // the two free slots come first with a new generation
+        let mut reused: Vec<usize> = second[..2].iter().map(|index| index.index()).collect();
+        reused.sort_unstable();
+        assert_eq!(reused, vec![1, 3]);
+        assert!(second[..2].iter().all(|index| index.generation() == 1));
+        assert_eq!(second[2], GenerationalIndex::new(5, 0));
+        assert_eq!(second[3], GenerationalIndex::new(6, 0));
+
+        for index in second {
+            assert!(allocator.is_live(index));
+        }
+        assert!(allocator.allocate_many(0).is_empty());
+    }
+
+    // compares allocate_many with single allocations; run with
+    // `cargo test --release -- --ignored --nocapture allocation_timing`
+    #[test]
+    #[ignore]
+    fn allocation_timing() {
+        use std::time::Instant;
+
+        const COUNT: usize = 1_000_000;
+        let start = Instant::now();
+        let mut single = GenerationalIndexAllocator::new();
+        let mut single_indices = Vec::with_capacity(COUNT);
+        for _ in 0..COUNT {
+            single_indices.push(single.allocate());
+        }
+        let single_time = start.elapsed();
+
+        let start = Instant::now();
+        let mut batched = GenerationalIndexAllocator::new();
+        let indices = batched.allocate_many(COUNT);
+        let batched_time = start.elapsed();
+
+        assert_eq!(indices, single_indices);
+        println!("allocate: {:?}, allocate_many: {:?}", single_time, batched_time);
+    }
+
+    #[test]
+    fn test_deallocate_batch() {
+        let mut allocator = GenerationalIndexAllocator::new();
+        let indices = allocator.allocate_many(6);
+        assert!(allocator.deallocate(indices[0]));
+        let stale = indices[0];
+        let reused = allocator.allocate();
+
+        // dead, stale and duplicate indices are not counted
+        let batch = [indices[1], indices[2], indices[2], stale, indices[5]];
+        assert_eq!(allocator.deallocate_batch(&batch), 3);
+        assert_eq!(allocator.live_count(), 3);
+        assert!(allocator.is_live(reused));
+        assert!(!allocator.is_live(indices[1]));
+        assert!(allocator.is_live(indices[3]));
+
+        assert_eq!(allocator.deallocate_batch(&batch), 0);
+        assert_eq!(allocator.free.len(), 3);
+    }
+
+    #[test]
+    fn test_generation_overflow() {
+        let mut allocator = GenerationalIndexAllocator::new();
+        let index = allocator.allocate();
+        allocator.entries[index.index()].generation = MAX_GENERATION - 1;
+        let index = GenerationalIndex::new(index.index(), MAX_GENERATION - 1);
+
+        assert!(allocator.deallocate(index));
+        let last = allocator.allocate();
+        assert_eq!(last, GenerationalIndex::new(0, MAX_GENERATION));
+
+        // the slot is exhausted, so a fresh one is used instead
+        assert!(allocator.deallocate(last));
+        let fresh = allocator.allocate();
+        assert_eq!(fresh, GenerationalIndex::new(1, 0));
+        assert!(!allocator.is_live(last));
+        assert!(!allocator.is_live(index));
+        assert!(allocator.free.is_empty());
+
+        // the retired slot is never handed out again
+        let many = allocator.allocate_many(3);
+        assert!(many.iter().all(|index| index.index() != 0));
+        assert_eq!(allocator.live_count(), 4);
+    }
+
+    #[test]
+    fn test_array_dirty_flags() {
+        let mut allocator = GenerationalIndexAllocator::new();
+        let mut array = GenerationalIndexArray::new();
+        let index_1 = allocator.allocate();
+        let index_2 = allocator.allocate();
+        array.set(index_1, 1);
+        array.set(index_2, 2);
+        assert!(array.is_dirty(index_1));
+
+        array.mark_clean(index_1);
+        assert!(!array.is_dirty(index_1));
+        assert!(array.is_dirty(index_2));
+
+        array.mark_all_clean();
+        assert!(!array.is_dirty(index_2));
+        let _ = array.get(index_2);
+        assert!(!array.is_dirty(index_2));
+
+        *array.get_mut(index_2).unwrap() += 1;
+        assert!(array.is_dirty(index_2));
+        assert!(!array.is_dirty(index_1));
+
+        for (_, value) in array.iter_mut() {
+            *value += 1;
+        }
+        assert!(array.is_dirty(index_1));
+    }
+
+    #[test]
+    fn test_array_occupied() {
+        let mut allocator = GenerationalIndexAllocator::new();
+        let mut array = GenerationalIndexArray::new();
+        let indices = allocator.allocate_many(4);
+        array.set(indices[0], 'a');
+        array.set(indices[2], 'c');
+        array.set(indices[3], 'd');
+        array.remove(indices[3]);
+
+        assert_eq!(array.len(), 2);
+        assert_eq!(array.occupied().iter().collect::<Vec<usize>>(), vec![0, 2]);
+        assert_eq!(array.get_slot(2), Some((indices[2], &'c')));
+        assert_eq!(array.get_slot(3), None);
+
+        array.delete(indices[0]);
+        assert_eq!(array.occupied().iter().collect::<Vec<usize>>(), vec![2]);
+    }
+
+    #[test]
+    fn test_null_index() {
+        let null = GenerationalIndex::null();
+        assert!(null.is_null());
+        assert_eq!(null, GenerationalIndex::null());
+        assert_eq!(hash_of(&null), hash_of(&GenerationalIndex::null()));
+
+        let mut allocator = GenerationalIndexAllocator::new();
+        assert!(!allocator.is_live(null));
+        assert!(!allocator.deallocate(null));
+        for index in allocator.allocate_many(10) {
+            assert!(!index.is_null());
+            assert_ne!(index, null);
+        }
+        assert!(!allocator.is_live(null));
+
+        let mut targets = HashMap::new();
+        targets.insert(null, "nobody");
+        assert_eq!(targets.get(&GenerationalIndex::null()), Some(&"nobody"));
+
+        let array: GenerationalIndexArray<u32> = GenerationalIndexArray::new();
+        assert_eq!(array.get(null), None);
+    }
+
+    #[test]
+    #[cfg(feature = "packed_index")]
+    fn test_packed_index_size() {
+        assert_eq!(std::mem::size_of::<GenerationalIndex>(), 8);
+
+        let index = GenerationalIndex::new(MAX_INDEX - 1, MAX_GENERATION);
+        assert_eq!(index.index(), MAX_INDEX - 1);
+        assert_eq!(index.generation(), MAX_GENERATION);
+        assert!(!index.is_null());
+    }
+
+    #[test]
+    fn test_index_ordering() {
+        let mut indices = vec![
+            GenerationalIndex::new(2, 0),
+            GenerationalIndex::new(0, 3),
+            GenerationalIndex::new(1, 1),
+            GenerationalIndex::new(0, 1),
+            GenerationalIndex::new(1, 0)
+        ];
+        indices.sort();
+        assert_eq!(indices, vec![
+            GenerationalIndex::new(0, 1),
+            GenerationalIndex::new(0, 3),
+            GenerationalIndex::new(1, 0),
+            GenerationalIndex::new(1, 1),
+            GenerationalIndex::new(2, 0)
+        ]);
+
+        let set: std::collections::BTreeSet<GenerationalIndex> = indices.iter().copied().collect();
+        assert_eq!(set.len(), 5);
+        assert_eq!(set.iter().next(), Some(&GenerationalIndex::new(0, 1)));
+    }
+
+    #[test]
+    fn test_bits_round_trip() {
+        let mut allocator = GenerationalIndexAllocator::new();
+        let indices = allocator.allocate_many(3);
+        allocator.deallocate(indices[1]);
+        let reused = allocator.allocate();
+
+        for index in indices.iter().chain(Some(&reused)) {
+            assert_eq!(index.to_bits().and_then(GenerationalIndex::from_bits), Some(*index));
+        }
+        assert_eq!(reused.to_bits(), Some((1 << 32) | 1));
+
+        #[cfg(not(feature = "packed_index"))]
+        let largest = GenerationalIndex::new(u32::MAX as usize, u32::MAX as u64);
+        #[cfg(feature = "packed_index")]
+        let largest = GenerationalIndex::new(MAX_INDEX - 1, u32::MAX as u64);
+        assert_eq!(largest.to_bits().and_then(GenerationalIndex::from_bits), Some(largest));
+    }
+
+    #[test]
+    fn test_to_bits_out_of_range() {
+        assert_eq!(GenerationalIndex::null().to_bits(), None);
+
+        // a live index whose generation just outgrew 32 bits
+        let mut allocator = GenerationalIndexAllocator::new();
+        let index = allocator.allocate();
+        allocator.entries[index.index()].generation = u64::from(u32::MAX);
+        let index = GenerationalIndex::new(index.index(), u64::from(u32::MAX));
+        assert_eq!(index.to_bits(), Some(u64::from(u32::MAX) << 32));
+
+        assert!(allocator.deallocate(index));
+        let next = allocator.allocate();
+        assert!(allocator.is_live(next));
+        assert_eq!(next.generation(), u64::from(u32::MAX) + 1);
+        assert_eq!(next.to_bits(), None);
+    }
+
+    #[test]
+    #[cfg(feature = "packed_index")]
+    fn test_from_bits_out_of_range() {
+        // indices beyond the 24 bit range cannot be represented
+        assert_eq!(GenerationalIndex::from_bits(u32::MAX as u64), None);
+    }
+
+    #[test]
+    fn test_array_get_many_mut() {
+        let mut allocator = GenerationalIndexAllocator::new();
+        let mut array = GenerationalIndexArray::new();
+        let indices = allocator.allocate_many(4);
+        for (value, index) in indices.iter().enumerate() {
+            array.set(*index, value);
+        }
+        array.mark_all_clean();
+
+        let values = array.get_many_mut(&[indices[3], indices[0], indices[2]]).unwrap();
+        assert_eq!(values.iter().map(|value| **value).collect::<Vec<usize>>(), vec![3, 0, 2]);
+        for value in values {
+            *value *= 10;
+        }
+        assert_eq!(array.get(indices[3]), Some(&30));
+        assert_eq!(array.get(indices[1]), Some(&1));
+        assert!(array.is_dirty(indices[0]));
+        assert!(!array.is_dirty(indices[1]));
+
+        assert_eq!(array.get_many_mut(&[]).map(|values| values.len()), Some(0));
+        // duplicates would alias
+        assert!(array.get_many_mut(&[indices[1], indices[2], indices[1]]).is_none());
+        // stale and missing indices
+        array.remove(indices[2]);
+        assert!(array.get_many_mut(&[indices[0], indices[2]]).is_none());
+        allocator.deallocate(indices[0]);
+        let reused = allocator.allocate();
+        assert!(array.get_many_mut(&[reused]).is_none());
+    }
+
+    #[test]
+    fn test_defragment() {
+        let mut allocator = GenerationalIndexAllocator::new();
+        let indices = allocator.allocate_many(8);
+        for slot in [0, 2, 3, 6].iter() {
+            allocator.deallocate(indices[*slot]);
+        }
+
+        let moved = allocator.defragment();
+        let mapping: HashMap<GenerationalIndex, GenerationalIndex> = moved.iter().copied().collect();
+        assert_eq!(moved.len(), 3);
+        assert_eq!(allocator.live_count(), 4);
+
+        let live_before = [1, 4, 5, 7];
+        for slot in live_before.iter() {
+            let old = indices[*slot];
+            let new = mapping.get(&old).copied().unwrap_or(old);
+            assert!(allocator.is_live(new));
+            if new != old {
+                assert!(!allocator.is_live(old));
+            }
+        }
+        let live_slots: Vec<usize> = allocator.live_indices().map(|index| index.index()).collect();
+        assert_eq!(live_slots, vec![0, 1, 2, 3]);
+        // dead indices of the reused slots stay dead
+        assert!(!allocator.is_live(indices[0]));
+        assert!(!allocator.is_live(indices[2]));
+
+        // new indices go to the lowest free slot
+        assert_eq!(allocator.allocate().index(), 4);
+        assert!(allocator.defragment().is_empty());
+    }
+
+    #[test]
+    fn test_fragmentation_ratio() {
+        let mut allocator = GenerationalIndexAllocator::new();
+        assert_eq!(allocator.fragmentation_ratio(), 0.0);
+
+        let indices = allocator.allocate_many(4);
+        assert_eq!(allocator.fragmentation_ratio(), 0.0);
+
+        allocator.deallocate(indices[1]);
+        assert_eq!(allocator.fragmentation_ratio(), 0.25);
+        allocator.deallocate(indices[3]);
+        assert_eq!(allocator.fragmentation_ratio(), 0.5);
+        allocator.deallocate_batch(&[indices[0], indices[2]]);
+        assert_eq!(allocator.fragmentation_ratio(), 1.0);
+
+        allocator.allocate();
+        assert_eq!(allocator.fragmentation_ratio(), 0.75);
+    }
+
+    #[test]
+    fn test_array_shrink_to_fit() {
+        let mut allocator = GenerationalIndexAllocator::new();
+        let mut array = GenerationalIndexArray::new();
+        let indices = allocator.allocate_many(200);
+        for (value, index) in indices.iter().enumerate() {
+            array.set(*index, value);
+        }
+        for index in indices[10..].iter() {
+            array.remove(*index);
+        }
+        array.remove(indices[3]);
+        let capacity_before = array.slots.capacity();
+
+        array.shrink_to_fit();
+        assert_eq!(array.slot_count(), 10);
+        assert!(array.slots.capacity() < capacity_before);
+        assert_eq!(array.len(), 9);
+        assert_eq!(array.get(indices[9]), Some(&9));
+        assert_eq!(array.get(indices[3]), None);
+        assert_eq!(array.occupied().iter().collect::<Vec<usize>>(), vec![0, 1, 2, 4, 5, 6, 7, 8, 9]);
+
+        // removed slots can be filled again
+        array.set(indices[150], 150);
+        assert_eq!(array.get(indices[150]), Some(&150));
+
+        let mut empty: GenerationalIndexArray<u32> = GenerationalIndexArray::new();
+        empty.shrink_to_fit();
+        assert_eq!(empty.slot_count(), 0);
+    }
+
+    #[test]
+    fn test_array_reserve() {
+        let mut allocator = GenerationalIndexAllocator::new();
+        let mut array = GenerationalIndexArray::new();
+        let indices = allocator.allocate_many(100);
+
+        array.reserve(indices.len());
+        let capacity = array.capacity();
+        assert!(capacity >= 100);
+        for (value, index) in indices.iter().enumerate() {
+            array.set(*index, value);
+            assert_eq!(array.capacity(), capacity);
+        }
+        assert_eq!(array.len(), 100);
+    }
+
+    #[test]
+    fn test_array_clear() {
+        let mut allocator = GenerationalIndexAllocator::new();
+        let mut array = GenerationalIndexArray::new();
+        let indices = allocator.allocate_many(3);
+        for index in indices.iter() {
+            array.set(*index, index.index());
+        }
+
+        array.clear();
+        assert!(array.is_empty());
+        assert!(array.occupied().is_empty());
+        assert_eq!(array.iter().count(), 0);
+        assert_eq!(array.get(indices[0]), None);
+
+        array.set(indices[1], 1);
+        assert_eq!(array.len(), 1);
+    }
 }
@@ -0,0 +1,262 @@
+use std::any::TypeId;
+
+use crate::ecs::{Entity, ECS};
+
+/// The set of component types an entity has. Entities with the same set
+/// share an archetype, regardless of the order the components were added
+/// in.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct Archetype {
+    // sorted and without duplicates
+    component_types: Vec<TypeId>,
+}
+
+// archetype of entities without any component
+static EMPTY: Archetype = Archetype {
+    component_types: Vec::new()
+};
+
+impl Archetype {
+    /// Duplicate types are ignored.
+    pub fn new(mut component_types: Vec<TypeId>) -> Archetype {
+        component_types.sort_unstable();
+        component_types.dedup();
+        Archetype {
+            component_types
+        }
+    }
+
+    /// The component types in an unspecified but fixed order.
+    pub fn component_types(&self) -> &[TypeId] {
+        &self.component_types
+    }
+
+    pub fn contains(&self, type_id: TypeId) -> bool {
+        self.component_types.binary_search(&type_id).is_ok()
+    }
+
+    /// Number of component types.
+    pub fn len(&self) -> usize {
+        self.component_types.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.component_types.is_empty()
+    }
+
+    /// The archetype with `type_id` added.
+    pub fn with(&self, type_id: TypeId) -> Archetype {
+        let mut component_types = self.component_types.clone();
+        if let Err(position) = component_types.binary_search(&type_id) {
+            component_types.insert(position, type_id);
+        }
+        Archetype {
+            component_types
+        }
+    }
+
+    /// The archetype with `type_id` removed.
+    pub fn without(&self, type_id: TypeId) -> Archetype {
+        let mut component_types = self.component_types.clone();
+        if let Ok(position) = component_types.binary_search(&type_id) {
+            component_types.remove(position);
+        }
+        Archetype {
+            component_types
+        }
+    }
+}
+
+impl ECS {
+    /// The archetype of the entity's current components. Entities without
+    /// components, including dead ones, have the empty archetype.
+    pub fn entity_archetype(&self, entity: Entity) -> &Archetype {
+        self.archetypes().get(entity).unwrap_or(&EMPTY)
+    }
+
+    /// All live entities with exactly the component types of `archetype`.
+    pub fn entities_with_archetype(&self, archetype: &Archetype) -> Vec<Entity> {
+        self.entities()
+            .filter(|entity| self.entity_archetype(*entity) == archetype)
+            .collect()
+    }
+
+    /// Read-only view on the entities of `archetype` and their
+    /// components, column by column.
+    pub fn query_archetype(&self, archetype: &Archetype) -> ArchetypeQuery<'_> {
+        ArchetypeQuery {
+            ecs: self,
+            archetype: archetype.clone(),
+            entities: self.entities_with_archetype(archetype)
+        }
+    }
+}
+
+/// The entities sharing one archetype, see `ECS::query_archetype`.
+/// Iterating yields the entities; `column` lines up with that order.
+pub struct ArchetypeQuery<'a> {
+    ecs: &'a ECS,
+    archetype: Archetype,
+    entities: Vec<Entity>,
+}
+
+impl<'a> ArchetypeQuery<'a> {
+    pub fn archetype(&self) -> &Archetype {
+        &self.archetype
+    }
+
+    pub fn entities(&self) -> &[Entity] {
+        &self.entities
+    }
+
+    pub fn len(&self) -> usize {
+        self.entities.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entities.is_empty()
+    }
+
+    /// The `T` component of each entity, in entity order. Returns `None`
+    /// if `T` is not part of the archetype.
+    pub fn column<T: 'static>(&self) -> Option<Vec<&'a T>> {
+        if !self.archetype.contains(TypeId::of::<T>()) {
+            return None;
+        }
+        let components = self.ecs.components::<T>()?;
+        // every entity of the archetype has a T
+        Some(self.entities.iter().map(|entity| components.get(*entity).unwrap()).collect())
+    }
+}
+
+impl<'a> IntoIterator for ArchetypeQuery<'a> {
+    type Item = Entity;
+    type IntoIter = std::vec::IntoIter<Entity>;
+
+    fn into_iter(self) -> std::vec::IntoIter<Entity> {
+        self.entities.into_iter()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Position;
+    struct Velocity;
+
+    #[test]
+    fn test_archetype() {
+        let position = TypeId::of::<Position>();
+        let velocity = TypeId::of::<Velocity>();
+        let archetype = Archetype::new(vec![velocity, position, velocity]);
+
+        assert_eq!(archetype, Archetype::new(vec![position, velocity]));
+        assert_eq!(archetype.len(), 2);
+        assert!(archetype.contains(position));
+        assert_eq!(archetype.without(velocity), Archetype::new(vec![position]));
+        assert_eq!(archetype.without(velocity).with(velocity), archetype);
+        assert!(archetype.without(position).without(velocity).is_empty());
+    }
+
+    #[test]
+    fn test_archetype_membership() {
+        let mut ecs = ECS::new();
+        let entity = ecs.create_entity();
+        let other = ecs.create_entity();
+        assert!(ecs.entity_archetype(entity).is_empty());
+
+        ecs.add_component(entity, Position);
+        ecs.add_component(entity, Velocity);
+        ecs.add_component(other, Velocity);
+        ecs.add_component(other, Position);
+        let both = Archetype::new(vec![TypeId::of::<Position>(), TypeId::of::<Velocity>()]);
+        assert_eq!(ecs.entity_archetype(entity), &both);
+        assert_eq!(ecs.entities_with_archetype(&both), vec![entity, other]);
+
+        ecs.remove_component::<Velocity>(other);
+        assert_eq!(ecs.entity_archetype(other), &Archetype::new(vec![TypeId::of::<Position>()]));
+        assert_eq!(ecs.entities_with_archetype(&both), vec![entity]);
+
+        ecs.destroy_entity(entity);
+        assert!(ecs.entity_archetype(entity).is_empty());
+        assert!(ecs.entities_with_archetype(&both).is_empty());
+
+        ecs.clear_components_only();
+        assert!(ecs.entity_archetype(other).is_empty());
+        assert_eq!(ecs.entities_with_archetype(&Archetype::default()), vec![other]);
+    }
+
+    #[test]
+    fn test_archetype_after_restore() {
+        let mut ecs = ECS::new();
+        ecs.register_cloneable_component::<u32>();
+        let entity = ecs.create_entity();
+        ecs.add_component(entity, 1u32);
+        let snapshot = ecs.checkpoint();
+
+        ecs.remove_component::<u32>(entity);
+        ecs.add_component(entity, Position);
+        ecs.restore(snapshot);
+        assert_eq!(ecs.entity_archetype(entity), &Archetype::new(vec![TypeId::of::<u32>()]));
+    }
+
+    #[test]
+    fn test_query_archetype() {
+        let mut ecs = ECS::new();
+        let first = ecs.spawn().with_component(1u32).with_component(Position).build();
+        ecs.spawn().with_component(2u32).build();
+        let second = ecs.spawn().with_component(Position).with_component(3u32).build();
+
+        let archetype = Archetype::new(vec![TypeId::of::<u32>(), TypeId::of::<Position>()]);
+        let query = ecs.query_archetype(&archetype);
+        assert_eq!(query.len(), 2);
+        assert_eq!(query.entities(), &[first, second]);
+        assert_eq!(query.column::<u32>(), Some(vec![&1, &3]));
+        assert_eq!(query.column::<Position>().map(|column| column.len()), Some(2));
+        assert!(query.column::<Velocity>().is_none());
+        assert_eq!(query.into_iter().collect::<Vec<Entity>>(), vec![first, second]);
+
+        assert!(ecs.query_archetype(&Archetype::new(vec![TypeId::of::<Velocity>()])).is_empty());
+    }
+
+    // baseline for archetype storage; run with
+    // `cargo test --release -- --ignored --nocapture iteration_timing`
+    #[test]
+    #[ignore]
+    fn iteration_timing() {
+        use std::time::Instant;
+
+        let mut ecs = ECS::new();
+        for i in 0..100_000u64 {
+            let entity = ecs.create_entity();
+            ecs.add_component(entity, i);
+            if i % 2 == 0 {
+                ecs.add_component(entity, i as u32);
+            }
+            if i % 3 == 0 {
+                ecs.add_component(entity, Position);
+            }
+        }
+        let archetype = Archetype::new(vec![TypeId::of::<u64>(), TypeId::of::<u32>()]);
+
+        let start = Instant::now();
+        let sparse: u64 = ecs.query2::<u64, u32>().into_iter()
+            .filter(|(entity, _, _)| ecs.entity_archetype(*entity) == &archetype)
+            .map(|(_, a, b)| a + u64::from(*b))
+            .sum();
+        let sparse_time = start.elapsed();
+
+        let start = Instant::now();
+        let query = ecs.query_archetype(&archetype);
+        let by_archetype: u64 = query.column::<u64>().unwrap().into_iter()
+            .zip(query.column::<u32>().unwrap())
+            .map(|(a, b)| a + u64::from(*b))
+            .sum();
+        let archetype_time = start.elapsed();
+
+        assert_eq!(sparse, by_archetype);
+        println!("query2: {:?}, query_archetype: {:?}", sparse_time, archetype_time);
+    }
+}
@@ -0,0 +1,221 @@
+const BITS: usize = 64;
+
+/// Set of slot indices stored as one bit per slot.
+///
+/// Component storages keep one of these to record which slots are
+/// occupied, so joins over several component types can intersect the
+/// bitsets instead of probing every storage for every entity.
+#[derive(Clone, Debug, Default)]
+pub struct GenerationalIndexBitSet {
+    words: Vec<u64>,
+}
+
+impl GenerationalIndexBitSet {
+    pub fn new() -> GenerationalIndexBitSet {
+        GenerationalIndexBitSet {
+            words: Vec::new()
+        }
+    }
+
+    pub fn set(&mut self, index: usize) {
+        let word = index / BITS;
+        if self.words.len() <= word {
+            self.words.resize(word + 1, 0);
+        }
+        self.words[word] |= 1 << (index % BITS);
+    }
+
+    pub fn clear(&mut self, index: usize) {
+        if let Some(word) = self.words.get_mut(index / BITS) {
+            *word &= !(1 << (index % BITS));
+        }
+    }
+
+    pub fn contains(&self, index: usize) -> bool {
+        match self.words.get(index / BITS) {
+            Some(word) => word & (1 << (index % BITS)) != 0,
+            None => false
+        }
+    }
+
+    /// Number of set bits.
+    pub fn len(&self) -> usize {
+        self.words.iter().map(|word| word.count_ones() as usize).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.words.iter().all(|word| *word == 0)
+    }
+
+    pub fn union(&self, other: &GenerationalIndexBitSet) -> GenerationalIndexBitSet {
+        let (longer, shorter) = if self.words.len() >= other.words.len() {
+            (self, other)
+        } else {
+            (other, self)
+        };
+        let mut words = longer.words.clone();
+        for (word, other_word) in words.iter_mut().zip(shorter.words.iter()) {
+            *word |= other_word;
+        }
+        GenerationalIndexBitSet {
+            words
+        }
+    }
+
+    pub fn intersection(&self, other: &GenerationalIndexBitSet) -> GenerationalIndexBitSet {
+        GenerationalIndexBitSet {
+            words: self.words.iter().zip(other.words.iter()).map(|(a, b)| a & b).collect()
+        }
+    }
+
+    pub fn difference(&self, other: &GenerationalIndexBitSet) -> GenerationalIndexBitSet {
+        let mut words = self.words.clone();
+        for (word, other_word) in words.iter_mut().zip(other.words.iter()) {
+            *word &= !other_word;
+        }
+        GenerationalIndexBitSet {
+            words
+        }
+    }
+
+    /// Drops trailing words without set bits and releases unused memory.
+    pub fn shrink_to_fit(&mut self) {
+        let used = self.words.iter().rposition(|word| *word != 0).map_or(0, |last| last + 1);
+        self.words.truncate(used);
+        self.words.shrink_to_fit();
+    }
+
+    /// Iterates over the set indices in ascending order.
+    pub fn iter(&self) -> Iter<'_> {
+        Iter {
+            words: &self.words,
+            position: BitPosition::default()
+        }
+    }
+}
+
+impl IntoIterator for GenerationalIndexBitSet {
+    type Item = usize;
+    type IntoIter = IntoIter;
+
+    fn into_iter(self) -> IntoIter {
+        IntoIter {
+            words: self.words,
+            position: BitPosition::default()
+        }
+    }
+}
+
+// iteration state shared by Iter and IntoIter
+#[derive(Default)]
+struct BitPosition {
+    // index of the next word to load into `bits`
+    next_word: usize,
+    // bits of the current word not yet yielded
+    bits: u64,
+}
+
+impl BitPosition {
+    fn next(&mut self, words: &[u64]) -> Option<usize> {
+        while self.bits == 0 {
+            self.bits = *words.get(self.next_word)?;
+            self.next_word += 1;
+        }
+        let bit = self.bits.trailing_zeros() as usize;
+        // drop the lowest set bit
+        self.bits &= self.bits - 1;
+        Some((self.next_word - 1) * BITS + bit)
+    }
+}
+
+pub struct Iter<'a> {
+    words: &'a [u64],
+    position: BitPosition,
+}
+
+impl<'a> Iterator for Iter<'a> {
+    type Item = usize;
+
+    fn next(&mut self) -> Option<usize> {
+        self.position.next(self.words)
+    }
+}
+
+pub struct IntoIter {
+    words: Vec<u64>,
+    position: BitPosition,
+}
+
+impl Iterator for IntoIter {
+    type Item = usize;
+
+    fn next(&mut self) -> Option<usize> {
+        self.position.next(&self.words)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bitset_of(indices: &[usize]) -> GenerationalIndexBitSet {
+        let mut bitset = GenerationalIndexBitSet::new();
+        for index in indices {
+            bitset.set(*index);
+        }
+        bitset
+    }
+
+    #[test]
+    fn test_set_clear_contains() {
+        let mut bitset = GenerationalIndexBitSet::new();
+        assert!(!bitset.contains(0));
+        assert!(bitset.is_empty());
+
+        bitset.set(0);
+        bitset.set(63);
+        bitset.set(64);
+        bitset.set(200);
+        assert!(bitset.contains(0));
+        assert!(bitset.contains(63));
+        assert!(bitset.contains(64));
+        assert!(bitset.contains(200));
+        assert!(!bitset.contains(1));
+        assert!(!bitset.contains(1000));
+        assert_eq!(bitset.len(), 4);
+
+        bitset.clear(63);
+        bitset.clear(1000);
+        assert!(!bitset.contains(63));
+        assert_eq!(bitset.iter().collect::<Vec<usize>>(), vec![0, 64, 200]);
+    }
+
+    #[test]
+    fn test_set_operations() {
+        let a = bitset_of(&[1, 5, 70, 130]);
+        let b = bitset_of(&[5, 6, 130]);
+
+        assert_eq!(a.union(&b).iter().collect::<Vec<usize>>(), vec![1, 5, 6, 70, 130]);
+        assert_eq!(b.union(&a).iter().collect::<Vec<usize>>(), vec![1, 5, 6, 70, 130]);
+        assert_eq!(a.intersection(&b).iter().collect::<Vec<usize>>(), vec![5, 130]);
+        assert_eq!(a.difference(&b).iter().collect::<Vec<usize>>(), vec![1, 70]);
+        assert_eq!(b.difference(&a).iter().collect::<Vec<usize>>(), vec![6]);
+        assert!(a.intersection(&GenerationalIndexBitSet::new()).is_empty());
+        assert_eq!(a.into_iter().collect::<Vec<usize>>(), vec![1, 5, 70, 130]);
+    }
+
+    #[test]
+    fn test_shrink_to_fit() {
+        let mut bitset = bitset_of(&[3, 100, 500]);
+        bitset.clear(500);
+        bitset.clear(100);
+        bitset.shrink_to_fit();
+        assert_eq!(bitset.words.len(), 1);
+        assert_eq!(bitset.iter().collect::<Vec<usize>>(), vec![3]);
+
+        bitset.clear(3);
+        bitset.shrink_to_fit();
+        assert!(bitset.words.is_empty());
+    }
+}
@@ -0,0 +1,61 @@
+use crate::ecs::{Entity, ECS};
+
+/// A group of components that is added to an entity in one go.
+///
+/// Implemented for tuples of up to eight components, e.g.
+/// `ecs.spawn_with_bundle((Position(0.0, 0.0), Velocity(1.0, 0.0)))`.
+pub trait ComponentBundle {
+    fn insert(self, ecs: &mut ECS, entity: Entity);
+}
+
+macro_rules! impl_component_bundle {
+    ($($component:ident),+) => {
+        impl<$($component: 'static),+> ComponentBundle for ($($component,)+) {
+            #[allow(non_snake_case)]
+            fn insert(self, ecs: &mut ECS, entity: Entity) {
+                let ($($component,)+) = self;
+                $(ecs.add_component(entity, $component);)+
+            }
+        }
+    };
+}
+
+impl_component_bundle!(A);
+impl_component_bundle!(A, B);
+impl_component_bundle!(A, B, C);
+impl_component_bundle!(A, B, C, D);
+impl_component_bundle!(A, B, C, D, E);
+impl_component_bundle!(A, B, C, D, E, F);
+impl_component_bundle!(A, B, C, D, E, F, G);
+impl_component_bundle!(A, B, C, D, E, F, G, H);
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, PartialEq)]
+    struct Position(f32, f32);
+    #[derive(Debug, PartialEq)]
+    struct Velocity(f32, f32);
+
+    #[test]
+    fn test_spawn_with_bundle() {
+        let mut ecs = ECS::new();
+        let entity = ecs.spawn_with_bundle((Position(0.0, 0.0), Velocity(1.0, 0.0)));
+
+        assert!(ecs.is_alive(entity));
+        assert_eq!(ecs.get_component::<Position>(entity), Some(&Position(0.0, 0.0)));
+        assert_eq!(ecs.get_component::<Velocity>(entity), Some(&Velocity(1.0, 0.0)));
+    }
+
+    #[test]
+    fn test_bundle_on_existing_entity() {
+        let mut ecs = ECS::new();
+        let entity = ecs.create_entity();
+        (1u8, 2u16, 3u32, 4u64, 5i8, 6i16, 7i32, 8i64).insert(&mut ecs, entity);
+
+        assert_eq!(ecs.get_component::<u8>(entity), Some(&1));
+        assert_eq!(ecs.get_component::<i64>(entity), Some(&8));
+    }
+}
@@ -0,0 +1,136 @@
+use std::any::TypeId;
+
+use crate::bundle::ComponentBundle;
+use crate::ecs::{Entity, ECS};
+
+// inserts type erased components into the world
+type Insert = Box<dyn FnOnce(&mut ECS, Entity)>;
+
+enum Command {
+    SpawnEntity(Insert),
+    DespawnEntity(Entity),
+    AddComponent { entity: Entity, component: Insert },
+    RemoveComponent { entity: Entity, type_id: TypeId },
+}
+
+/// Records changes to the world so they can be applied after iterating,
+/// see `ECS::apply_commands`.
+pub struct Commands {
+    commands: Vec<Command>,
+}
+
+impl Commands {
+    pub fn new() -> Commands {
+        Commands {
+            commands: Vec::new()
+        }
+    }
+
+    /// Spawns an entity with all components of the bundle.
+    pub fn spawn<B: ComponentBundle + 'static>(&mut self, bundle: B) {
+        self.commands.push(Command::SpawnEntity(Box::new(move |ecs, entity| bundle.insert(ecs, entity))));
+    }
+
+    pub fn despawn(&mut self, entity: Entity) {
+        self.commands.push(Command::DespawnEntity(entity));
+    }
+
+    pub fn add_component<T: 'static>(&mut self, entity: Entity, component: T) {
+        self.commands.push(Command::AddComponent {
+            entity,
+            component: Box::new(move |ecs, entity| ecs.add_component(entity, component))
+        });
+    }
+
+    pub fn remove_component<T: 'static>(&mut self, entity: Entity) {
+        self.commands.push(Command::RemoveComponent {
+            entity,
+            type_id: TypeId::of::<T>()
+        });
+    }
+
+    /// Number of recorded commands.
+    pub fn len(&self) -> usize {
+        self.commands.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.commands.is_empty()
+    }
+
+    // applies the commands in the order they were recorded; commands on
+    // entities that died in the meantime are skipped
+    pub(crate) fn apply(self, ecs: &mut ECS) {
+        for command in self.commands {
+            match command {
+                Command::SpawnEntity(insert) => {
+                    let entity = ecs.create_entity();
+                    insert(ecs, entity);
+                },
+                Command::DespawnEntity(entity) => {
+                    ecs.destroy_entity(entity);
+                },
+                Command::AddComponent { entity, component } => {
+                    if ecs.is_alive(entity) {
+                        component(ecs, entity);
+                    }
+                },
+                Command::RemoveComponent { entity, type_id } => {
+                    ecs.remove_component_by_type_id(entity, type_id);
+                }
+            }
+        }
+    }
+}
+
+impl Default for Commands {
+    fn default() -> Commands {
+        Commands::new()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Health(u32);
+    struct Dead;
+
+    #[test]
+    fn test_commands_after_query() {
+        let mut ecs = ECS::new();
+        let alive = ecs.spawn_with_bundle((Health(10),));
+        let dying = ecs.spawn_with_bundle((Health(0),));
+
+        // tick 1: record changes while iterating
+        let mut commands = Commands::new();
+        for (entity, health) in ecs.query::<Health>() {
+            if health.0 == 0 {
+                commands.add_component(entity, Dead);
+                commands.remove_component::<Health>(entity);
+                commands.spawn((Health(5),));
+            }
+        }
+        assert_eq!(commands.len(), 3);
+        assert!(!ecs.has_component::<Dead>(dying));
+        ecs.apply_commands(commands);
+
+        // tick 2: the changes are visible
+        assert!(ecs.has_component::<Dead>(dying));
+        assert!(!ecs.has_component::<Health>(dying));
+        assert_eq!(ecs.query::<Health>().into_iter().count(), 2);
+        assert_eq!(ecs.entity_count(), 3);
+
+        let mut commands = Commands::new();
+        for (entity, _) in ecs.query::<Dead>() {
+            commands.despawn(entity);
+            // the entity is gone by the time this is applied
+            commands.add_component(entity, Health(1));
+        }
+        ecs.apply_commands(commands);
+        assert!(!ecs.is_alive(dying));
+        assert!(ecs.is_alive(alive));
+        assert_eq!(ecs.entity_count(), 2);
+    }
+}
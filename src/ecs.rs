@@ -0,0 +1,2211 @@
+use std::any::{self, Any, TypeId};
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::mem;
+use std::rc::Rc;
+
+use crate::allocation::{GenerationalIndex, GenerationalIndexAllocator, GenerationalIndexArray};
+use crate::archetype::Archetype;
+use crate::bundle::ComponentBundle;
+use crate::commands::Commands;
+use crate::event::Events;
+use crate::name::{name_of, Name};
+use crate::query::{Query, Query2, Query2Mut, Query3, QueryMut};
+
+pub type Entity = GenerationalIndex;
+
+pub type EntityMap<T> = GenerationalIndexArray<T>;
+
+// type erased view on an EntityMap<T>, so that operations spanning all
+// component types do not need to know T
+pub(crate) trait ComponentStorage: Any {
+    fn contains_entity(&self, entity: Entity) -> bool;
+    fn remove_entity(&mut self, entity: Entity);
+    // rough estimate of the memory held by the component values
+    fn approximate_bytes(&self) -> usize;
+    // moves the component of each mapped entity to its counterpart in `ecs`
+    fn move_into(&mut self, ecs: &mut ECS, mapping: &HashMap<Entity, Entity>);
+    fn take_entity(&mut self, entity: Entity) -> Option<Box<dyn Any>>;
+    fn type_name(&self) -> &'static str;
+    fn component_count(&self) -> usize;
+    fn clear(&mut self);
+}
+
+impl<T: 'static> ComponentStorage for EntityMap<T> {
+    fn contains_entity(&self, entity: Entity) -> bool {
+        self.contains(entity)
+    }
+
+    fn remove_entity(&mut self, entity: Entity) {
+        self.remove(entity);
+    }
+
+    fn approximate_bytes(&self) -> usize {
+        self.slot_count() * mem::size_of::<T>()
+    }
+
+    fn move_into(&mut self, ecs: &mut ECS, mapping: &HashMap<Entity, Entity>) {
+        for (source, target) in mapping {
+            if let Some(component) = self.remove(*source) {
+                ecs.add_component(*target, component);
+            }
+        }
+    }
+
+    fn take_entity(&mut self, entity: Entity) -> Option<Box<dyn Any>> {
+        let component = self.remove(entity)?;
+        Some(Box::new(component))
+    }
+
+    fn type_name(&self) -> &'static str {
+        any::type_name::<T>()
+    }
+
+    fn component_count(&self) -> usize {
+        self.len()
+    }
+
+    fn clear(&mut self) {
+        GenerationalIndexArray::clear(self);
+    }
+}
+
+type StorageCloner = fn(&dyn ComponentStorage) -> Box<dyn ComponentStorage>;
+
+type AddHook = Box<dyn Fn(&mut ECS, Entity)>;
+
+// gets the removed component as `&dyn Any`, see `register_on_remove`
+type RemoveHook = Rc<dyn Fn(&mut ECS, Entity, &dyn Any)>;
+
+// shared, so the list can be copied before running and a hook can destroy
+// further entities
+type DestroyHook = Rc<dyn Fn(&mut ECS, Entity)>;
+
+/// Summary of entity and memory usage, see `ECS::world_stats`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct WorldStats {
+    pub live_entities: usize,
+    /// Allocated entity slots that are currently not alive.
+    pub dead_slots: usize,
+    pub component_types: usize,
+    /// Size of each component type times the slots of its storage.
+    pub approximate_bytes: usize,
+}
+
+/// A component type registered via `ECS::register_component`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ComponentStorageInfo {
+    pub type_name: &'static str,
+    pub type_id: TypeId,
+    /// Number of entities that currently have the component.
+    pub count: usize,
+}
+
+/// Why `ECS::try_single` found no single match.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SingleError {
+    NoEntities,
+    MultipleEntities,
+}
+
+impl fmt::Display for SingleError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SingleError::NoEntities => write!(f, "no entity has the component"),
+            SingleError::MultipleEntities => write!(f, "more than one entity has the component")
+        }
+    }
+}
+
+impl Error for SingleError {}
+
+/// Game logic that operates on the whole world, e.g. once per frame.
+pub trait System {
+    fn run(&mut self, ecs: &mut ECS);
+}
+
+#[allow(clippy::upper_case_acronyms)]
+pub struct ECS {
+    entity_allocator: GenerationalIndexAllocator,
+    // one EntityMap<T> per component type, keyed by the TypeId of T
+    entity_components: HashMap<TypeId, Box<dyn ComponentStorage>>,
+    // global values that do not belong to any entity, at most one per type
+    resources: HashMap<TypeId, Box<dyn Any>>,
+    // swaps the buffers of the Events<T> resource, one per event type
+    event_updaters: HashMap<TypeId, fn(&mut ECS)>,
+    // copies a component from the first to the second entity, one per
+    // component type registered as cloneable
+    component_cloners: HashMap<TypeId, fn(&mut ECS, Entity, Entity)>,
+    // copies a whole storage, one per component type registered as
+    // cloneable
+    storage_cloners: HashMap<TypeId, StorageCloner>,
+    // called after a component of the keyed type was added to an entity
+    on_add_hooks: HashMap<TypeId, Vec<AddHook>>,
+    // called after a component of the keyed type was removed from an entity
+    on_remove_hooks: HashMap<TypeId, Vec<RemoveHook>>,
+    // called after an entity was destroyed, in registration order
+    on_destroy_hooks: Vec<DestroyHook>,
+    // component types and their names in registration order
+    registered_components: Vec<(TypeId, &'static str)>,
+    // archetype of each entity that has at least one component
+    entity_archetypes: EntityMap<Archetype>,
+}
+
+impl ECS {
+    pub fn new() -> ECS {
+        ECS {
+            entity_allocator: GenerationalIndexAllocator::new(),
+            entity_components: HashMap::new(),
+            resources: HashMap::new(),
+            event_updaters: HashMap::new(),
+            component_cloners: HashMap::new(),
+            storage_cloners: HashMap::new(),
+            on_add_hooks: HashMap::new(),
+            on_remove_hooks: HashMap::new(),
+            on_destroy_hooks: Vec::new(),
+            registered_components: Vec::new(),
+            entity_archetypes: EntityMap::new()
+        }
+    }
+
+    /// Creates a new entity. The returned handle stays valid until
+    /// `destroy_entity` is called on it.
+    pub fn create_entity(&mut self) -> Entity {
+        self.entity_allocator.allocate()
+    }
+
+    /// Starts building a new entity, e.g.
+    /// `ecs.spawn().with_component(Position(0.0)).build()`.
+    pub fn spawn(&mut self) -> EntityBuilder<'_> {
+        let entity = self.create_entity();
+        EntityBuilder {
+            ecs: self,
+            entity
+        }
+    }
+
+    /// Creates a new entity with all components of the bundle.
+    pub fn spawn_with_bundle<B: ComponentBundle>(&mut self, bundle: B) -> Entity {
+        let entity = self.create_entity();
+        bundle.insert(self, entity);
+        entity
+    }
+
+    /// Creates `count` entities at once, see
+    /// `GenerationalIndexAllocator::allocate_many`.
+    pub fn create_entities(&mut self, count: usize) -> Vec<Entity> {
+        self.entity_allocator.allocate_many(count)
+    }
+
+    /// Lets `clone_entity` copy and `checkpoint` capture components of
+    /// type `T`.
+    pub fn register_cloneable_component<T: Clone + 'static>(&mut self) {
+        self.component_cloners.insert(TypeId::of::<T>(), |ecs, source, target| {
+            if let Some(component) = ecs.get_component::<T>(source).cloned() {
+                ecs.add_component(target, component);
+            }
+        });
+        self.register_storage_cloner::<T>();
+    }
+
+    // lets `checkpoint` capture components of type `T`, without making
+    // `clone_entity` copy them
+    pub(crate) fn register_storage_cloner<T: Clone + 'static>(&mut self) {
+        self.storage_cloners.insert(TypeId::of::<T>(), |storage| {
+            // only called with the storage of T
+            let components = (storage as &dyn Any).downcast_ref::<EntityMap<T>>().unwrap();
+            Box::new(components.clone())
+        });
+    }
+
+    /// Creates a new entity with a copy of each of the source's components
+    /// whose type was registered via `register_cloneable_component`. Other
+    /// components are not copied. Returns `None` if the source is dead.
+    pub fn clone_entity(&mut self, source: Entity) -> Option<Entity> {
+        if !self.is_alive(source) {
+            return None;
+        }
+
+        let target = self.create_entity();
+        let cloners: Vec<fn(&mut ECS, Entity, Entity)> = self.component_cloners.values().copied().collect();
+        for clone_component in cloners {
+            clone_component(self, source, target);
+        }
+        Some(target)
+    }
+
+    /// Captures all entities, including their generations, and their
+    /// components. Only components of types registered via
+    /// `register_cloneable_component` are captured; resources and events
+    /// are not part of the snapshot.
+    pub fn checkpoint(&self) -> WorldSnapshot {
+        let entity_components = self.entity_components.iter()
+            .filter_map(|(type_id, storage)| {
+                let clone_storage = self.storage_cloners.get(type_id)?;
+                Some((*type_id, clone_storage(storage.as_ref())))
+            })
+            .collect();
+        WorldSnapshot {
+            entity_allocator: self.entity_allocator.clone(),
+            entity_components
+        }
+    }
+
+    /// Replaces all entities and components with the ones captured by
+    /// `checkpoint`. Components of types that were not captured are
+    /// dropped.
+    pub fn restore(&mut self, snapshot: WorldSnapshot) {
+        self.entity_allocator = snapshot.entity_allocator;
+        self.entity_components = snapshot.entity_components;
+        self.rebuild_archetypes();
+        self.rebuild_name_index();
+    }
+
+    /// Moves all entities and components of `other` into this world. The
+    /// entities get new handles here; the returned map goes from the old
+    /// handle in `other` to the new one, so components referencing
+    /// entities can be patched. `Parent`, `Children` and relations are
+    /// patched already. Resources and events of `other` are dropped.
+    pub fn merge(&mut self, mut other: ECS) -> HashMap<Entity, Entity> {
+        let mapping: HashMap<Entity, Entity> = other.entities()
+            .map(|source| (source, self.create_entity()))
+            .collect();
+        for storage in other.entity_components.values_mut() {
+            storage.move_into(self, &mapping);
+        }
+        self.remap_hierarchy(&mapping);
+        self.remap_relations(&other, &mapping);
+        mapping
+    }
+
+    /// Destroys the entity and drops all of its components. Returns `false`
+    /// if the entity was not alive. Children of the entity lose their
+    /// `Parent` but stay alive.
+    pub fn destroy_entity(&mut self, entity: Entity) -> bool {
+        if !self.is_alive(entity) {
+            return false;
+        }
+
+        self.detach_from_hierarchy(entity);
+        self.remove_name(entity);
+        // components with hooks are removed while the entity is still alive
+        let hooked: Vec<TypeId> = self.on_remove_hooks.keys().copied().collect();
+        for type_id in hooked {
+            self.remove_component_by_type_id(entity, type_id);
+        }
+        self.entity_allocator.deallocate(entity);
+        self.entity_archetypes.remove(entity);
+
+        for storage in self.entity_components.values_mut() {
+            storage.remove_entity(entity);
+        }
+
+        let hooks = self.on_destroy_hooks.clone();
+        for hook in hooks {
+            hook(self, entity);
+        }
+        true
+    }
+
+    /// Calls `hook` whenever a live entity is destroyed, after its
+    /// components were removed. Hooks run in registration order.
+    pub fn register_on_destroy<F: Fn(&mut ECS, Entity) + 'static>(&mut self, hook: F) {
+        self.on_destroy_hooks.push(Rc::new(hook));
+    }
+
+    /// Destroys all entities and drops all components at once, e.g. to
+    /// reload a level. Resources, registrations and hooks are kept, but no
+    /// hooks run. Like `GenerationalIndexAllocator::clear`, this restarts
+    /// generations, so handles from before may alias new entities.
+    pub fn clear(&mut self) {
+        self.entity_allocator.clear();
+        self.entity_archetypes.clear();
+        for storage in self.entity_components.values_mut() {
+            storage.clear();
+        }
+        self.rebuild_name_index();
+    }
+
+    /// Drops all components but keeps every entity alive, so existing
+    /// handles stay valid. No hooks run.
+    pub fn clear_components_only(&mut self) {
+        self.entity_archetypes.clear();
+        for storage in self.entity_components.values_mut() {
+            storage.clear();
+        }
+        self.rebuild_name_index();
+    }
+
+    /// Destroys every live entity for which the predicate returns `false`.
+    /// The predicate gets read access to the world, e.g. to check a
+    /// component, and sees it before any entity is destroyed.
+    pub fn retain_entities<F: FnMut(&ECS, Entity) -> bool>(&mut self, mut predicate: F) {
+        let rejected: Vec<Entity> = self.entities()
+            .filter(|entity| !predicate(self, *entity))
+            .collect();
+        for entity in rejected {
+            self.destroy_entity(entity);
+        }
+    }
+
+    /// Checks whether the entity is alive. `false` means the entity was
+    /// either destroyed or never allocated by this world.
+    pub fn is_alive(&self, entity: Entity) -> bool {
+        self.entity_allocator.is_live(entity)
+    }
+
+    /// Number of live entities.
+    pub fn entity_count(&self) -> usize {
+        self.entity_allocator.live_count()
+    }
+
+    /// Iterates over all live entities. The order is unspecified; currently
+    /// it is ascending slot order.
+    pub fn entities(&self) -> impl Iterator<Item = Entity> + '_ {
+        self.entity_allocator.live_indices()
+    }
+
+    /// Aggregate counts for profiling and debug output.
+    pub fn world_stats(&self) -> WorldStats {
+        let live_entities = self.entity_allocator.live_count();
+        WorldStats {
+            live_entities,
+            dead_slots: self.entity_allocator.total_count() - live_entities,
+            component_types: self.entity_components.len(),
+            approximate_bytes: self.entity_components.values().map(|storage| storage.approximate_bytes()).sum()
+        }
+    }
+
+    /// Adds the component to the entity, replacing any previous component
+    /// of the same type. Adding to a dead entity is a bug; it panics in
+    /// debug builds and is ignored in release builds.
+    pub fn add_component<T: 'static>(&mut self, entity: Entity, component: T) {
+        debug_assert!(self.is_alive(entity), "add_component on dead entity");
+        if !self.is_alive(entity) {
+            return;
+        }
+        if !self.entity_components.contains_key(&TypeId::of::<T>()) {
+            self.register_component::<T>();
+        }
+        let added_name = name_of(&component);
+        let components = self.components_or_insert::<T>();
+        let is_new = !components.contains(entity);
+        let replaced_name = components.get(entity).and_then(|current| name_of(current));
+        components.set(entity, component);
+        self.name_changed(entity, replaced_name, added_name);
+        if is_new {
+            self.archetype_gained(entity, TypeId::of::<T>());
+            self.run_on_add_hooks(TypeId::of::<T>(), entity);
+        }
+    }
+
+    /// Calls `hook` whenever an entity gains a component of type `T` it did
+    /// not have before. Replacing an existing component does not count.
+    /// Hooks run in registration order; while they run, adding another `T`
+    /// does not call them again.
+    pub fn register_on_add<T: 'static, F: Fn(&mut ECS, Entity) + 'static>(&mut self, hook: F) {
+        self.on_add_hooks.entry(TypeId::of::<T>()).or_default().push(Box::new(hook));
+    }
+
+    fn run_on_add_hooks(&mut self, type_id: TypeId, entity: Entity) {
+        // taken out while running, which also stops a hook from recursing
+        let hooks = match self.on_add_hooks.remove(&type_id) {
+            Some(hooks) => hooks,
+            None => return
+        };
+        for hook in hooks.iter() {
+            hook(self, entity);
+        }
+        put_back_hooks(&mut self.on_add_hooks, type_id, hooks);
+    }
+
+    /// Calls `hook` with the old value whenever a component of type `T` is
+    /// removed from an entity, including when the entity is destroyed.
+    /// The component is already gone from the entity when the hook runs.
+    /// Unlike add hooks, a hook may remove further `T`s, e.g. to cascade
+    /// removals down a chain of entities; the hooks run for each of them.
+    pub fn register_on_remove<T: 'static, F: Fn(&mut ECS, Entity, &T) + 'static>(&mut self, hook: F) {
+        self.on_remove_hooks.entry(TypeId::of::<T>()).or_default().push(Rc::new(move |ecs, entity, component| {
+            // registered under the TypeId of T
+            hook(ecs, entity, component.downcast_ref::<T>().unwrap())
+        }));
+    }
+
+    fn run_on_remove_hooks(&mut self, type_id: TypeId, entity: Entity, component: &dyn Any) {
+        // cloned rather than taken out, so that removals made by a hook run
+        // the hooks as well; this ends since each removal needs a component
+        let hooks = match self.on_remove_hooks.get(&type_id) {
+            Some(hooks) => hooks.clone(),
+            None => return
+        };
+        for hook in hooks {
+            hook(self, entity, component);
+        }
+    }
+
+    /// Like `add_component`, but returns the component it replaced.
+    pub fn add_or_replace_component<T: 'static>(&mut self, entity: Entity, component: T) -> Option<T> {
+        let added_name = name_of(&component);
+        match self.get_component_mut::<T>(entity) {
+            Some(current) => {
+                let replaced = mem::replace(current, component);
+                self.name_changed(entity, name_of(&replaced), added_name);
+                Some(replaced)
+            },
+            None => {
+                self.add_component(entity, component);
+                None
+            }
+        }
+    }
+
+    /// Returns the entity's component of type `T`, if it has one.
+    pub fn get_component<T: 'static>(&self, entity: Entity) -> Option<&T> {
+        self.components::<T>()?.get(entity)
+    }
+
+    /// Mutable counterpart of `get_component`.
+    pub fn get_component_mut<T: 'static>(&mut self, entity: Entity) -> Option<&mut T> {
+        self.components_mut::<T>()?.get_mut(entity)
+    }
+
+    /// Applies `f` to the entity's component of type `T`. Returns `false`
+    /// if the entity has no such component.
+    pub fn modify_component<T: 'static, F: FnOnce(&mut T)>(&mut self, entity: Entity, f: F) -> bool {
+        match self.get_component_mut::<T>(entity) {
+            Some(component) => {
+                f(component);
+                true
+            },
+            None => false
+        }
+    }
+
+    /// Mutable access to two components of the same entity at once.
+    /// Panics if `A` and `B` are the same type. No unsafe code is needed:
+    /// the two components live in the storages of different types, which
+    /// `HashMap::get_disjoint_mut` hands out side by side.
+    pub fn get_two_components_mut<A: 'static, B: 'static>(&mut self, entity: Entity) -> Option<(&mut A, &mut B)> {
+        assert_ne!(TypeId::of::<A>(), TypeId::of::<B>(), "get_two_components_mut needs distinct component types");
+        let [a, b] = self.entity_components.get_disjoint_mut([&TypeId::of::<A>(), &TypeId::of::<B>()]);
+        let a = (a?.as_mut() as &mut dyn Any).downcast_mut::<EntityMap<A>>().unwrap();
+        let b = (b?.as_mut() as &mut dyn Any).downcast_mut::<EntityMap<B>>().unwrap();
+        // checked first, since mutable access marks components as changed
+        if !a.contains(entity) || !b.contains(entity) {
+            return None;
+        }
+        Some((a.get_mut(entity)?, b.get_mut(entity)?))
+    }
+
+    /// Removes the entity's component of type `T` and returns it. The
+    /// entity itself stays alive.
+    pub fn remove_component<T: 'static>(&mut self, entity: Entity) -> Option<T> {
+        let component = self.components_mut::<T>()?.remove(entity)?;
+        self.archetype_lost(entity, TypeId::of::<T>());
+        self.name_changed(entity, name_of(&component), None);
+        self.run_on_remove_hooks(TypeId::of::<T>(), entity, &component);
+        Some(component)
+    }
+
+    /// Moves the entity's component of type `T` out of the world. Same as
+    /// `remove_component`, but reads better where ownership is handed on.
+    pub fn take_component<T: 'static>(&mut self, entity: Entity) -> Option<T> {
+        self.remove_component::<T>(entity)
+    }
+
+    /// Exchanges the `T` components of two entities. Returns `false` and
+    /// changes nothing unless both entities have one.
+    pub fn swap_components<T: 'static>(&mut self, entity_a: Entity, entity_b: Entity) -> bool {
+        if !self.has_component::<T>(entity_a) || !self.has_component::<T>(entity_b) {
+            return false;
+        }
+        if entity_a == entity_b {
+            return true;
+        }
+
+        // both are present, checked above; works on the storage directly
+        // since no entity gains or loses a component
+        let components = self.components_mut::<T>().unwrap();
+        let a = components.remove(entity_a).unwrap();
+        let b = components.remove(entity_b).unwrap();
+        let (name_a, name_b) = (name_of(&a), name_of(&b));
+        components.set(entity_a, b);
+        components.set(entity_b, a);
+        self.name_changed(entity_a, name_a.clone(), name_b.clone());
+        self.name_changed(entity_b, name_b, name_a);
+        true
+    }
+
+    /// Moves the `T` component of `from` to `to`, replacing any `T` that
+    /// `to` already had. Returns `false` and changes nothing if `from` has
+    /// no such component or `to` is dead.
+    pub fn move_component<T: 'static>(&mut self, from: Entity, to: Entity) -> bool {
+        if !self.has_component::<T>(from) || !self.is_alive(to) {
+            return false;
+        }
+
+        // present, checked above
+        let component = self.take_component::<T>(from).unwrap();
+        self.add_component(to, component);
+        true
+    }
+
+    /// Removes the `T` component from every entity for which the predicate
+    /// returns `false`. The entities themselves stay alive.
+    pub fn retain_component<T: 'static, F: FnMut(Entity, &T) -> bool>(&mut self, mut predicate: F) {
+        let rejected: Vec<Entity> = match self.components::<T>() {
+            Some(components) => components.iter()
+                .filter(|(entity, component)| !predicate(*entity, component))
+                .map(|(entity, _)| entity)
+                .collect(),
+            None => return
+        };
+        for entity in rejected {
+            self.remove_component::<T>(entity);
+        }
+    }
+
+    pub(crate) fn remove_component_by_type_id(&mut self, entity: Entity, type_id: TypeId) {
+        let storage = match self.entity_components.get_mut(&type_id) {
+            Some(storage) if storage.contains_entity(entity) => storage,
+            _ => return
+        };
+        // only box the component if a hook or the name index wants to see it
+        if self.on_remove_hooks.contains_key(&type_id) || type_id == TypeId::of::<Name>() {
+            // present, checked above
+            let component = storage.take_entity(entity).unwrap();
+            self.archetype_lost(entity, type_id);
+            self.name_changed(entity, name_of(component.as_ref()), None);
+            self.run_on_remove_hooks(type_id, entity, component.as_ref());
+        } else {
+            storage.remove_entity(entity);
+            self.archetype_lost(entity, type_id);
+        }
+    }
+
+    /// Checks whether the entity has a component of type `T`.
+    pub fn has_component<T: 'static>(&self, entity: Entity) -> bool {
+        match self.components::<T>() {
+            Some(components) => components.contains(entity),
+            None => false
+        }
+    }
+
+    /// Makes `T` known to the world before any component of that type is
+    /// added, see `component_infos`. `add_component` registers types on
+    /// first use; registering twice has no effect.
+    pub fn register_component<T: 'static>(&mut self) {
+        self.components_or_insert::<T>();
+        let type_id = TypeId::of::<T>();
+        if !self.registered_components.iter().any(|(registered, _)| *registered == type_id) {
+            self.registered_components.push((type_id, any::type_name::<T>()));
+        }
+    }
+
+    /// Name, `TypeId` and current count of each registered component type,
+    /// in registration order.
+    pub fn component_infos(&self) -> Vec<ComponentStorageInfo> {
+        self.registered_components.iter()
+            .map(|(type_id, type_name)| ComponentStorageInfo {
+                type_name,
+                type_id: *type_id,
+                count: self.storage(*type_id).map_or(0, |storage| storage.component_count())
+            })
+            .collect()
+    }
+
+    /// Number of entities that have a component of type `T`.
+    pub fn component_count<T: 'static>(&self) -> usize {
+        self.components::<T>().map_or(0, |components| components.len())
+    }
+
+    /// Number of entities a `query::<T>()` visits, in constant time.
+    pub fn query_count<T: 'static>(&self) -> usize {
+        self.component_count::<T>()
+    }
+
+    /// Whether any entity has a component of type `T`.
+    pub fn any_with<T: 'static>(&self) -> bool {
+        self.query_count::<T>() > 0
+    }
+
+    /// Number of distinct component types the entity has.
+    pub fn entity_component_count(&self, entity: Entity) -> usize {
+        self.entity_components.values().filter(|storage| storage.contains_entity(entity)).count()
+    }
+
+    /// Snapshot of all live entities that have a component of type `T`, in
+    /// ascending slot order.
+    pub fn entities_with_component<T: 'static>(&self) -> Vec<Entity> {
+        match self.components::<T>() {
+            Some(components) => components.iter()
+                .map(|(entity, _)| entity)
+                .filter(|entity| self.entity_allocator.is_live(*entity))
+                .collect(),
+            None => Vec::new()
+        }
+    }
+
+    /// Live entities that have a component of every listed type, for
+    /// callers that only know the types at runtime. An empty list matches
+    /// all live entities.
+    pub fn entities_with_all(&self, type_ids: &[TypeId]) -> Vec<Entity> {
+        let storages: Option<Vec<&dyn ComponentStorage>> = type_ids.iter()
+            .map(|type_id| self.storage(*type_id))
+            .collect();
+        // a type without storage has no entities at all
+        let storages = match storages {
+            Some(storages) => storages,
+            None => return Vec::new()
+        };
+        self.entities()
+            .filter(|entity| storages.iter().all(|storage| storage.contains_entity(*entity)))
+            .collect()
+    }
+
+    /// Read-only access to all entities that have a component of type `T`.
+    pub fn query<T: 'static>(&self) -> Query<'_, T> {
+        Query::new(self)
+    }
+
+    /// Mutable access to all entities that have a component of type `T`.
+    pub fn query_mut<T: 'static>(&mut self) -> QueryMut<'_, T> {
+        QueryMut::new(self.components_mut::<T>())
+    }
+
+    /// Read-only access to all entities that have both an `A` and a `B`.
+    pub fn query2<A: 'static, B: 'static>(&self) -> Query2<'_, A, B> {
+        Query2::new(self.components::<A>(), self.components::<B>())
+    }
+
+    /// Read-only access to all entities that have an `A`, a `B` and a `C`.
+    pub fn query3<A: 'static, B: 'static, C: 'static>(&self) -> Query3<'_, A, B, C> {
+        Query3::new(self.components::<A>(), self.components::<B>(), self.components::<C>())
+    }
+
+    /// All entities that have a component of type `T`, sorted by the key
+    /// of their component. Entities with equal keys keep slot order.
+    pub fn query_sorted<T: 'static, K: Ord, F: Fn(&T) -> K>(&self, key_fn: F) -> Vec<(Entity, &T)> {
+        let mut results: Vec<(Entity, &T)> = self.query::<T>().into_iter().collect();
+        results.sort_by_key(|(_, component)| key_fn(component));
+        results
+    }
+
+    /// Some entity that has a component of type `T`, for types only one
+    /// entity is expected to have. Which one is unspecified if there are
+    /// several.
+    pub fn query_first<T: 'static>(&self) -> Option<(Entity, &T)> {
+        self.query::<T>().into_iter().next()
+    }
+
+    /// The component of the only entity that has a `T`. Panics if no or
+    /// several entities have one, see `try_single`.
+    pub fn single<T: 'static>(&self) -> &T {
+        match self.try_single::<T>() {
+            Ok(component) => component,
+            Err(error) => panic!("single::<{}>() failed: {}", any::type_name::<T>(), error)
+        }
+    }
+
+    /// Mutable counterpart of `single`.
+    pub fn single_mut<T: 'static>(&mut self) -> &mut T {
+        match self.try_single_mut::<T>() {
+            Ok(component) => component,
+            Err(error) => panic!("single_mut::<{}>() failed: {}", any::type_name::<T>(), error)
+        }
+    }
+
+    /// The component of the only entity that has a `T`.
+    pub fn try_single<T: 'static>(&self) -> Result<&T, SingleError> {
+        let mut components = self.query::<T>().into_iter().map(|(_, component)| component);
+        match (components.next(), components.next()) {
+            (Some(component), None) => Ok(component),
+            (None, _) => Err(SingleError::NoEntities),
+            (Some(_), Some(_)) => Err(SingleError::MultipleEntities)
+        }
+    }
+
+    /// Mutable counterpart of `try_single`.
+    pub fn try_single_mut<T: 'static>(&mut self) -> Result<&mut T, SingleError> {
+        // counted first, since mutable access marks components as changed
+        match self.component_count::<T>() {
+            0 => Err(SingleError::NoEntities),
+            // one component, so the query yields it
+            1 => Ok(self.query_mut::<T>().into_iter().next().unwrap().1),
+            _ => Err(SingleError::MultipleEntities)
+        }
+    }
+
+    /// All entities that have a component of type `T`, without the
+    /// component itself. Meant for tags such as `struct IsEnemy;`.
+    pub fn query_tagged<T: 'static>(&self) -> impl Iterator<Item = Entity> + '_ {
+        self.query::<T>().into_iter().map(|(entity, _)| entity)
+    }
+
+    /// Read-only access to all entities that have a `Required`, together
+    /// with their `Optional` if they have one.
+    pub fn query_with_option<Required: 'static, Optional: 'static>(&self) -> impl Iterator<Item = (Entity, &Required, Option<&Optional>)> + '_ {
+        let optional = self.components::<Optional>();
+        self.query::<Required>()
+            .into_iter()
+            .map(move |(entity, required)| (entity, required, optional.and_then(|components| components.get(entity))))
+    }
+
+    /// Mutable access to all entities that have both an `A` and a `B`.
+    /// Panics if `A` and `B` are the same type.
+    pub fn query2_mut<A: 'static, B: 'static>(&mut self) -> Query2Mut<'_, A, B> {
+        assert_ne!(TypeId::of::<A>(), TypeId::of::<B>(), "query2_mut needs distinct component types");
+        let [a, b] = self.entity_components.get_disjoint_mut([&TypeId::of::<A>(), &TypeId::of::<B>()]);
+        let a = a.map(|storage| (storage.as_mut() as &mut dyn Any).downcast_mut::<EntityMap<A>>().unwrap());
+        let b = b.map(|storage| (storage.as_mut() as &mut dyn Any).downcast_mut::<EntityMap<B>>().unwrap());
+        Query2Mut::new(a, b)
+    }
+
+    /// Iterates over the components of type `T` that were added or mutably
+    /// borrowed since the last `mark_components_clean::<T>`.
+    pub fn query_changed<T: 'static>(&self) -> impl Iterator<Item = (Entity, &T)> + '_ {
+        self.components::<T>()
+            .into_iter()
+            .flat_map(|components| components.iter().filter(move |(entity, _)| components.is_dirty(*entity)))
+    }
+
+    /// Resets change detection for all components of type `T`, usually
+    /// after a system has processed `query_changed`.
+    pub fn mark_components_clean<T: 'static>(&mut self) {
+        if let Some(components) = self.components_mut::<T>() {
+            components.mark_all_clean();
+        }
+    }
+
+    /// Calls `f` for every entity that has a component of type `T`.
+    pub fn for_each<T: 'static, F: FnMut(Entity, &T)>(&self, mut f: F) {
+        for (entity, component) in self.query::<T>() {
+            f(entity, component);
+        }
+    }
+
+    /// Mutable counterpart of `for_each`.
+    pub fn for_each_mut<T: 'static, F: FnMut(Entity, &mut T)>(&mut self, mut f: F) {
+        for (entity, component) in self.query_mut::<T>() {
+            f(entity, component);
+        }
+    }
+
+    /// Stores a global value, replacing any previous resource of type `T`.
+    pub fn insert_resource<T: 'static>(&mut self, value: T) {
+        self.resources.insert(TypeId::of::<T>(), Box::new(value));
+    }
+
+    pub fn get_resource<T: 'static>(&self) -> Option<&T> {
+        self.resources.get(&TypeId::of::<T>())?.downcast_ref::<T>()
+    }
+
+    pub fn get_resource_mut<T: 'static>(&mut self) -> Option<&mut T> {
+        self.resources.get_mut(&TypeId::of::<T>())?.downcast_mut::<T>()
+    }
+
+    pub fn remove_resource<T: 'static>(&mut self) -> Option<T> {
+        let resource = self.resources.remove(&TypeId::of::<T>())?;
+        // keyed by TypeId, so the downcast cannot fail
+        resource.downcast::<T>().ok().map(|resource| *resource)
+    }
+
+    /// Queues an event in the `Events<T>` resource. It becomes readable
+    /// after the next `update_events`.
+    pub fn send_event<T: 'static>(&mut self, event: T) {
+        if self.get_resource::<Events<T>>().is_none() {
+            self.insert_resource(Events::<T>::new());
+            self.event_updaters.insert(TypeId::of::<Events<T>>(), |ecs| {
+                if let Some(events) = ecs.get_resource_mut::<Events<T>>() {
+                    events.update();
+                }
+            });
+        }
+        // inserted above if it was missing
+        self.get_resource_mut::<Events<T>>().unwrap().send(event);
+    }
+
+    /// Iterates over the events of type `T` sent during the previous tick.
+    pub fn read_events<T: 'static>(&self) -> impl Iterator<Item = &T> + '_ {
+        self.get_resource::<Events<T>>()
+            .into_iter()
+            .flat_map(|events| events.read())
+    }
+
+    /// Ends the current tick for all event types. `Scheduler::run_all`
+    /// calls this after running its systems.
+    pub fn update_events(&mut self) {
+        let updaters: Vec<fn(&mut ECS)> = self.event_updaters.values().copied().collect();
+        for update in updaters {
+            update(self);
+        }
+    }
+
+    /// Applies the recorded commands in order.
+    pub fn apply_commands(&mut self, commands: Commands) {
+        commands.apply(self);
+    }
+
+    pub fn run_system(&mut self, system: &mut dyn System) {
+        system.run(self);
+    }
+
+    pub(crate) fn storage(&self, type_id: TypeId) -> Option<&dyn ComponentStorage> {
+        self.entity_components.get(&type_id).map(|storage| storage.as_ref())
+    }
+
+    pub(crate) fn components<T: 'static>(&self) -> Option<&EntityMap<T>> {
+        let storage: &dyn Any = self.entity_components.get(&TypeId::of::<T>())?.as_ref();
+        storage.downcast_ref::<EntityMap<T>>()
+    }
+
+    fn components_mut<T: 'static>(&mut self) -> Option<&mut EntityMap<T>> {
+        let storage: &mut dyn Any = self.entity_components.get_mut(&TypeId::of::<T>())?.as_mut();
+        storage.downcast_mut::<EntityMap<T>>()
+    }
+
+    pub(crate) fn archetypes(&self) -> &EntityMap<Archetype> {
+        &self.entity_archetypes
+    }
+
+    fn archetype_gained(&mut self, entity: Entity, type_id: TypeId) {
+        let archetype = self.entity_archetype(entity).with(type_id);
+        self.entity_archetypes.set(entity, archetype);
+    }
+
+    fn archetype_lost(&mut self, entity: Entity, type_id: TypeId) {
+        let archetype = self.entity_archetype(entity).without(type_id);
+        if archetype.is_empty() {
+            self.entity_archetypes.remove(entity);
+        } else {
+            self.entity_archetypes.set(entity, archetype);
+        }
+    }
+
+    // derives the archetypes from the storages, after they were replaced
+    fn rebuild_archetypes(&mut self) {
+        let mut archetypes = EntityMap::new();
+        for entity in self.entity_allocator.live_indices() {
+            let component_types: Vec<TypeId> = self.entity_components.iter()
+                .filter(|(_, storage)| storage.contains_entity(entity))
+                .map(|(type_id, _)| *type_id)
+                .collect();
+            if !component_types.is_empty() {
+                archetypes.set(entity, Archetype::new(component_types));
+            }
+        }
+        self.entity_archetypes = archetypes;
+    }
+
+    fn components_or_insert<T: 'static>(&mut self) -> &mut EntityMap<T> {
+        let storage: &mut dyn Any = self.entity_components
+            .entry(TypeId::of::<T>())
+            .or_insert_with(|| Box::new(EntityMap::<T>::new()))
+            .as_mut();
+        // the map is keyed by TypeId, so the downcast cannot fail
+        storage.downcast_mut::<EntityMap<T>>().unwrap()
+    }
+}
+
+// puts back hooks taken out for running; hooks registered by a hook in the
+// meantime go after the existing ones
+fn put_back_hooks<H>(hooks: &mut HashMap<TypeId, Vec<H>>, type_id: TypeId, mut taken: Vec<H>) {
+    if let Some(registered_meanwhile) = hooks.remove(&type_id) {
+        taken.extend(registered_meanwhile);
+    }
+    hooks.insert(type_id, taken);
+}
+
+// worlds with fewer entities list the components of each entity
+const DEBUG_ENTITY_LIMIT: usize = 64;
+
+impl fmt::Debug for ECS {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut component_types: Vec<&str> = self.entity_components.values()
+            .map(|storage| storage.type_name())
+            .collect();
+        component_types.sort_unstable();
+
+        let mut debug = f.debug_struct("ECS");
+        debug.field("live_entities", &self.entity_count())
+            .field("component_types", &component_types)
+            .field("resources", &self.resources.len());
+        if self.entity_count() < DEBUG_ENTITY_LIMIT {
+            debug.field("entities", &EntityListing(self));
+        }
+        debug.finish()
+    }
+}
+
+// maps each live entity to the names of its component types
+struct EntityListing<'a>(&'a ECS);
+
+impl<'a> fmt::Debug for EntityListing<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let ecs = self.0;
+        f.debug_map()
+            .entries(ecs.entities().map(|entity| {
+                let mut names: Vec<&str> = ecs.entity_components.values()
+                    .filter(|storage| storage.contains_entity(entity))
+                    .map(|storage| storage.type_name())
+                    .collect();
+                names.sort_unstable();
+                (entity, names)
+            }))
+            .finish()
+    }
+}
+
+impl Default for ECS {
+    fn default() -> ECS {
+        ECS::new()
+    }
+}
+
+/// Entities and components of a world at one point in time, see
+/// `ECS::checkpoint`.
+pub struct WorldSnapshot {
+    entity_allocator: GenerationalIndexAllocator,
+    entity_components: HashMap<TypeId, Box<dyn ComponentStorage>>,
+}
+
+/// Adds components to a freshly created entity, see `ECS::spawn`.
+pub struct EntityBuilder<'a> {
+    ecs: &'a mut ECS,
+    entity: Entity,
+}
+
+impl<'a> EntityBuilder<'a> {
+    pub fn with_component<T: 'static>(self, component: T) -> EntityBuilder<'a> {
+        self.ecs.add_component(self.entity, component);
+        self
+    }
+
+    pub fn build(self) -> Entity {
+        self.entity
+    }
+}
+
+/// Runs a list of systems. Systems run in the order they were added unless
+/// ordering constraints between their labels say otherwise.
+pub struct Scheduler {
+    systems: Vec<ScheduledSystem>,
+    // (before, after) label pairs
+    constraints: Vec<(String, String)>,
+}
+
+struct ScheduledSystem {
+    system: Box<dyn System>,
+    label: Option<String>,
+}
+
+impl Scheduler {
+    pub fn new() -> Scheduler {
+        Scheduler {
+            systems: Vec::new(),
+            constraints: Vec::new()
+        }
+    }
+
+    pub fn add_system(&mut self, system: Box<dyn System>) {
+        self.systems.push(ScheduledSystem {
+            system,
+            label: None
+        });
+    }
+
+    /// Adds a system that ordering constraints can refer to by `label`.
+    pub fn add_system_with_label(&mut self, system: Box<dyn System>, label: &str) {
+        self.systems.push(ScheduledSystem {
+            system,
+            label: Some(label.to_string())
+        });
+    }
+
+    /// Makes all systems labeled `before` run before those labeled `after`.
+    /// Constraints on labels without systems are ignored.
+    pub fn add_ordering_constraint(&mut self, before: &str, after: &str) {
+        self.constraints.push((before.to_string(), after.to_string()));
+    }
+
+    /// Removes the system at the position it was added at. Systems behind
+    /// it move up by one.
+    pub fn remove_system_by_index(&mut self, index: usize) -> Option<Box<dyn System>> {
+        if index < self.systems.len() {
+            Some(self.systems.remove(index).system)
+        } else {
+            None
+        }
+    }
+
+    /// Runs every system once and then ends the tick for all events. Panics
+    /// if the ordering constraints contain a cycle.
+    pub fn run_all(&mut self, ecs: &mut ECS) {
+        for index in self.execution_order() {
+            ecs.run_system(self.systems[index].system.as_mut());
+        }
+        ecs.update_events();
+    }
+
+    // topological sort of the systems; among the systems that are free to
+    // run the one added first goes first, so unconstrained systems keep
+    // their insertion order
+    fn execution_order(&self) -> Vec<usize> {
+        let count = self.systems.len();
+        let mut successors = vec![Vec::new(); count];
+        let mut predecessor_count = vec![0; count];
+
+        for (before, after) in self.constraints.iter() {
+            for (from, from_system) in self.systems.iter().enumerate() {
+                if from_system.label.as_ref() != Some(before) {
+                    continue;
+                }
+                for (to, to_system) in self.systems.iter().enumerate() {
+                    if to_system.label.as_ref() == Some(after) {
+                        successors[from].push(to);
+                        predecessor_count[to] += 1;
+                    }
+                }
+            }
+        }
+
+        let mut order = Vec::with_capacity(count);
+        let mut scheduled = vec![false; count];
+        while order.len() < count {
+            let next = (0..count).find(|&index| !scheduled[index] && predecessor_count[index] == 0);
+            let next = match next {
+                Some(next) => next,
+                None => panic!("cycle in system ordering constraints")
+            };
+
+            scheduled[next] = true;
+            order.push(next);
+            for &successor in successors[next].iter() {
+                predecessor_count[successor] -= 1;
+            }
+        }
+        order
+    }
+}
+
+impl Default for Scheduler {
+    fn default() -> Scheduler {
+        Scheduler::new()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    #[test]
+    fn test_new_world_is_empty() {
+        let ecs = ECS::new();
+        assert_eq!(ecs.entity_allocator.live_count(), 0);
+        assert!(ecs.entity_components.is_empty());
+    }
+
+    #[test]
+    fn test_create_entity() {
+        let mut ecs = ECS::new();
+        let entity_1 = ecs.create_entity();
+        let entity_2 = ecs.create_entity();
+
+        assert_ne!(entity_1, entity_2);
+        assert!(ecs.is_alive(entity_1));
+        assert!(ecs.is_alive(entity_2));
+    }
+
+    #[test]
+    fn test_destroy_entity() {
+        let mut ecs = ECS::new();
+        let entity_1 = ecs.create_entity();
+        let entity_2 = ecs.create_entity();
+
+        let mut numbers = EntityMap::new();
+        numbers.set(entity_1, 1u32);
+        numbers.set(entity_2, 2u32);
+        ecs.entity_components.insert(TypeId::of::<u32>(), Box::new(numbers));
+
+        assert!(ecs.destroy_entity(entity_1));
+        assert!(!ecs.is_alive(entity_1));
+        assert!(!ecs.destroy_entity(entity_1));
+
+        assert_eq!(ecs.get_component::<u32>(entity_1), None);
+        assert_eq!(ecs.get_component::<u32>(entity_2), Some(&2));
+    }
+
+    #[test]
+    fn test_add_component() {
+        let mut ecs = ECS::new();
+        let entity = ecs.create_entity();
+
+        ecs.add_component(entity, 1u32);
+        assert_eq!(ecs.get_component::<u32>(entity), Some(&1));
+
+        // same type replaces
+        ecs.add_component(entity, 2u32);
+        assert_eq!(ecs.get_component::<u32>(entity), Some(&2));
+
+        // different types live side by side
+        ecs.add_component(entity, "name");
+        assert_eq!(ecs.get_component::<u32>(entity), Some(&2));
+        assert_eq!(ecs.get_component::<&str>(entity), Some(&"name"));
+        assert_eq!(ecs.entity_components.len(), 2);
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "add_component on dead entity")]
+    fn test_add_component_dead_entity() {
+        let mut ecs = ECS::new();
+        let entity = ecs.create_entity();
+        ecs.destroy_entity(entity);
+        ecs.add_component(entity, 1u32);
+    }
+
+    #[test]
+    fn test_get_component() {
+        let mut ecs = ECS::new();
+        let entity = ecs.create_entity();
+        assert_eq!(ecs.get_component::<u32>(entity), None);
+
+        ecs.add_component(entity, 7u32);
+        assert_eq!(ecs.get_component::<u32>(entity), Some(&7));
+        assert_eq!(ecs.get_component::<i64>(entity), None);
+    }
+
+    #[test]
+    fn test_get_component_stale_entity() {
+        let mut ecs = ECS::new();
+        let old_entity = ecs.create_entity();
+        ecs.add_component(old_entity, 1u32);
+        ecs.destroy_entity(old_entity);
+
+        // the new entity reuses the slot, but must not see the old data
+        let new_entity = ecs.create_entity();
+        assert_eq!(new_entity.index(), old_entity.index());
+        assert_eq!(ecs.get_component::<u32>(new_entity), None);
+
+        ecs.add_component(new_entity, 2u32);
+        assert_eq!(ecs.get_component::<u32>(old_entity), None);
+        assert_eq!(ecs.get_component::<u32>(new_entity), Some(&2));
+    }
+
+    #[test]
+    fn test_get_component_mut() {
+        let mut ecs = ECS::new();
+        let entity = ecs.create_entity();
+        assert_eq!(ecs.get_component_mut::<u32>(entity), None);
+
+        ecs.add_component(entity, 10u32);
+        if let Some(value) = ecs.get_component_mut::<u32>(entity) {
+            *value += 5;
+        }
+        assert_eq!(ecs.get_component::<u32>(entity), Some(&15));
+    }
+
+    #[test]
+    fn test_remove_component() {
+        let mut ecs = ECS::new();
+        let entity = ecs.create_entity();
+        assert_eq!(ecs.remove_component::<u32>(entity), None);
+
+        ecs.add_component(entity, 3u32);
+        ecs.add_component(entity, 4i64);
+        assert_eq!(ecs.remove_component::<u32>(entity), Some(3));
+
+        assert!(ecs.is_alive(entity));
+        assert_eq!(ecs.get_component::<u32>(entity), None);
+        assert_eq!(ecs.remove_component::<u32>(entity), None);
+        assert_eq!(ecs.get_component::<i64>(entity), Some(&4));
+
+        ecs.destroy_entity(entity);
+        assert_eq!(ecs.remove_component::<i64>(entity), None);
+    }
+
+    #[test]
+    fn test_has_component() {
+        let mut ecs = ECS::new();
+        let entity = ecs.create_entity();
+
+        // no storage for the type yet
+        assert!(!ecs.has_component::<u32>(entity));
+
+        ecs.add_component(entity, 1u32);
+        assert!(ecs.has_component::<u32>(entity));
+        assert!(!ecs.has_component::<i64>(entity));
+
+        // the reused slot holds data again, but for a newer generation
+        ecs.destroy_entity(entity);
+        let new_entity = ecs.create_entity();
+        ecs.add_component(new_entity, 2u32);
+        assert_eq!(new_entity.index(), entity.index());
+        assert!(!ecs.has_component::<u32>(entity));
+        assert!(ecs.has_component::<u32>(new_entity));
+    }
+
+    #[test]
+    fn test_is_alive() {
+        let mut ecs = ECS::new();
+        let entity = ecs.create_entity();
+        assert!(ecs.is_alive(entity));
+
+        ecs.destroy_entity(entity);
+        assert!(!ecs.is_alive(entity));
+
+        // handle from another world
+        let mut other = ECS::new();
+        other.create_entity();
+        let foreign = other.create_entity();
+        assert!(!ecs.is_alive(foreign));
+    }
+
+    #[test]
+    fn test_entity_count_stress() {
+        let mut ecs = ECS::new();
+        let mut alive = Vec::new();
+        // small xorshift generator, good enough for shuffling test input
+        let mut state = 0x2545_f491_4f6c_dd1du64;
+        let mut next_random = move || {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state
+        };
+
+        for _ in 0..5000 {
+            if alive.is_empty() || next_random() % 3 != 0 {
+                alive.push(ecs.create_entity());
+            } else {
+                let position = (next_random() % alive.len() as u64) as usize;
+                let entity = alive.swap_remove(position);
+                assert!(ecs.destroy_entity(entity));
+                assert!(!ecs.destroy_entity(entity));
+            }
+            assert_eq!(ecs.entity_count(), alive.len());
+        }
+
+        for entity in alive.drain(..) {
+            ecs.destroy_entity(entity);
+        }
+        assert_eq!(ecs.entity_count(), 0);
+    }
+
+    #[test]
+    fn test_entities() {
+        let mut ecs = ECS::new();
+        let created: Vec<Entity> = (0..100).map(|_| ecs.create_entity()).collect();
+        for entity in created.iter().step_by(2) {
+            ecs.destroy_entity(*entity);
+        }
+
+        let entities: Vec<Entity> = ecs.entities().collect();
+        assert_eq!(entities.len(), 50);
+        for entity in entities {
+            assert!(ecs.is_alive(entity));
+            assert_eq!(entity.index() % 2, 1);
+        }
+    }
+
+    #[test]
+    fn test_for_each() {
+        let mut ecs = ECS::new();
+        let mut entities = Vec::new();
+        for i in 1..=5u32 {
+            let entity = ecs.create_entity();
+            ecs.add_component(entity, i);
+            entities.push(entity);
+        }
+
+        ecs.for_each_mut::<u32, _>(|_, value| *value *= 2);
+
+        let mut seen = Vec::new();
+        ecs.for_each::<u32, _>(|entity, value| seen.push((entity, *value)));
+        let expected: Vec<(Entity, u32)> = entities.iter().zip(1..=5u32).map(|(e, i)| (*e, i * 2)).collect();
+        assert_eq!(seen, expected);
+    }
+
+    struct Counter(u32);
+
+    struct IncrementSystem;
+
+    impl System for IncrementSystem {
+        fn run(&mut self, ecs: &mut ECS) {
+            ecs.for_each_mut::<Counter, _>(|_, counter| counter.0 += 1);
+        }
+    }
+
+    struct LogSystem {
+        log: Rc<RefCell<Vec<(Entity, u32)>>>,
+    }
+
+    impl System for LogSystem {
+        fn run(&mut self, ecs: &mut ECS) {
+            let mut log = self.log.borrow_mut();
+            ecs.for_each::<Counter, _>(|entity, counter| log.push((entity, counter.0)));
+        }
+    }
+
+    #[test]
+    fn test_run_system() {
+        let mut ecs = ECS::new();
+        let entity_1 = ecs.create_entity();
+        let entity_2 = ecs.create_entity();
+        ecs.add_component(entity_1, Counter(0));
+        ecs.add_component(entity_2, Counter(10));
+
+        let log = Rc::new(RefCell::new(Vec::new()));
+        let mut systems: Vec<Box<dyn System>> = vec![
+            Box::new(IncrementSystem),
+            Box::new(LogSystem { log: log.clone() })
+        ];
+
+        for _ in 0..2 {
+            for system in systems.iter_mut() {
+                ecs.run_system(system.as_mut());
+            }
+        }
+
+        assert_eq!(ecs.get_component::<Counter>(entity_1).map(|c| c.0), Some(2));
+        assert_eq!(*log.borrow(), vec![(entity_1, 1), (entity_2, 11), (entity_1, 2), (entity_2, 12)]);
+    }
+
+    struct RecordSystem {
+        name: &'static str,
+        log: Rc<RefCell<Vec<&'static str>>>,
+    }
+
+    impl System for RecordSystem {
+        fn run(&mut self, _ecs: &mut ECS) {
+            self.log.borrow_mut().push(self.name);
+        }
+    }
+
+    #[test]
+    fn test_scheduler_order() {
+        let mut ecs = ECS::new();
+        let log = Rc::new(RefCell::new(Vec::new()));
+        let mut scheduler = Scheduler::new();
+        for name in ["first", "second", "third"].iter() {
+            scheduler.add_system(Box::new(RecordSystem { name, log: log.clone() }));
+        }
+
+        scheduler.run_all(&mut ecs);
+        assert_eq!(*log.borrow(), vec!["first", "second", "third"]);
+
+        assert!(scheduler.remove_system_by_index(1).is_some());
+        assert!(scheduler.remove_system_by_index(2).is_none());
+        log.borrow_mut().clear();
+        scheduler.run_all(&mut ecs);
+        assert_eq!(*log.borrow(), vec!["first", "third"]);
+    }
+
+    #[test]
+    fn test_scheduler_diamond_constraints() {
+        let mut ecs = ECS::new();
+        let log = Rc::new(RefCell::new(Vec::new()));
+        let mut scheduler = Scheduler::new();
+        for name in ["d", "c", "b", "a"].iter() {
+            scheduler.add_system_with_label(Box::new(RecordSystem { name, log: log.clone() }), name);
+        }
+        scheduler.add_system(Box::new(RecordSystem { name: "unlabeled", log: log.clone() }));
+
+        scheduler.add_ordering_constraint("a", "b");
+        scheduler.add_ordering_constraint("a", "c");
+        scheduler.add_ordering_constraint("b", "d");
+        scheduler.add_ordering_constraint("c", "d");
+        scheduler.add_ordering_constraint("a", "missing");
+
+        scheduler.run_all(&mut ecs);
+        assert_eq!(*log.borrow(), vec!["a", "c", "b", "d", "unlabeled"]);
+    }
+
+    #[test]
+    #[should_panic(expected = "cycle in system ordering constraints")]
+    fn test_scheduler_cycle() {
+        let mut ecs = ECS::new();
+        let log = Rc::new(RefCell::new(Vec::new()));
+        let mut scheduler = Scheduler::new();
+        scheduler.add_system_with_label(Box::new(RecordSystem { name: "a", log: log.clone() }), "a");
+        scheduler.add_system_with_label(Box::new(RecordSystem { name: "b", log }), "b");
+        scheduler.add_ordering_constraint("a", "b");
+        scheduler.add_ordering_constraint("b", "a");
+        scheduler.run_all(&mut ecs);
+    }
+
+    struct Gravity(f32);
+
+    #[test]
+    fn test_resources() {
+        let mut ecs = ECS::new();
+        assert!(ecs.get_resource::<Gravity>().is_none());
+
+        ecs.insert_resource(Gravity(9.81));
+        ecs.insert_resource(0.016f64);
+        assert_eq!(ecs.get_resource::<Gravity>().map(|g| g.0), Some(9.81));
+
+        if let Some(gravity) = ecs.get_resource_mut::<Gravity>() {
+            gravity.0 = 1.62;
+        }
+        assert_eq!(ecs.get_resource::<Gravity>().map(|g| g.0), Some(1.62));
+
+        // resources do not depend on any entity
+        let entity = ecs.create_entity();
+        ecs.add_component(entity, Gravity(0.0));
+        ecs.destroy_entity(entity);
+        assert_eq!(ecs.get_resource::<Gravity>().map(|g| g.0), Some(1.62));
+        assert_eq!(ecs.get_resource::<f64>(), Some(&0.016));
+
+        ecs.insert_resource(Gravity(3.71));
+        assert_eq!(ecs.get_resource::<Gravity>().map(|g| g.0), Some(3.71));
+    }
+
+    #[test]
+    fn test_remove_resource() {
+        let mut ecs = ECS::new();
+        assert!(ecs.remove_resource::<Gravity>().is_none());
+
+        ecs.insert_resource(Gravity(9.81));
+        assert_eq!(ecs.remove_resource::<Gravity>().map(|g| g.0), Some(9.81));
+        assert!(ecs.get_resource::<Gravity>().is_none());
+        assert!(ecs.remove_resource::<Gravity>().is_none());
+
+        ecs.insert_resource(Gravity(1.62));
+        assert_eq!(ecs.get_resource::<Gravity>().map(|g| g.0), Some(1.62));
+    }
+
+    #[test]
+    fn test_create_entities() {
+        let mut ecs = ECS::new();
+        let entities = ecs.create_entities(100);
+        assert_eq!(ecs.entity_count(), 100);
+        assert!(entities.iter().all(|entity| ecs.is_alive(*entity)));
+    }
+
+    #[test]
+    fn test_spawn_builder() {
+        let mut ecs = ECS::new();
+        let entity = ecs.spawn()
+            .with_component(Counter(3))
+            .with_component(Gravity(9.81))
+            .with_component("player")
+            .build();
+
+        assert!(ecs.is_alive(entity));
+        assert_eq!(ecs.get_component::<Counter>(entity).map(|c| c.0), Some(3));
+        assert_eq!(ecs.get_component::<Gravity>(entity).map(|g| g.0), Some(9.81));
+        assert_eq!(ecs.get_component::<&str>(entity), Some(&"player"));
+
+        let empty = ecs.spawn().build();
+        assert!(ecs.is_alive(empty));
+        assert_ne!(empty, entity);
+    }
+
+    #[test]
+    fn test_clone_entity() {
+        let mut ecs = ECS::new();
+        ecs.register_cloneable_component::<Name>();
+        ecs.register_cloneable_component::<u32>();
+
+        let template = ecs.spawn()
+            .with_component(Name("goblin".to_string()))
+            .with_component(5u32)
+            .with_component(Counter(1))
+            .build();
+
+        let copy = ecs.clone_entity(template).unwrap();
+        assert_ne!(copy, template);
+        assert_eq!(ecs.get_component::<Name>(copy), Some(&Name("goblin".to_string())));
+        assert_eq!(ecs.get_component::<u32>(copy), Some(&5));
+        // not registered as cloneable
+        assert!(!ecs.has_component::<Counter>(copy));
+
+        ecs.get_component_mut::<Name>(copy).unwrap().0.push_str(" king");
+        *ecs.get_component_mut::<u32>(copy).unwrap() = 50;
+        assert_eq!(ecs.get_component::<Name>(template), Some(&Name("goblin".to_string())));
+        assert_eq!(ecs.get_component::<u32>(template), Some(&5));
+        // the copy was renamed in place, which the lookup does not follow
+        assert_eq!(ecs.entity_by_name("goblin"), Some(template));
+        ecs.remove_name(template);
+        assert_eq!(ecs.entity_by_name("goblin"), None);
+
+        ecs.destroy_entity(template);
+        assert_eq!(ecs.clone_entity(template), None);
+    }
+
+    #[test]
+    fn test_query_changed() {
+        let mut ecs = ECS::new();
+        let entities = ecs.create_entities(4);
+        for (i, entity) in entities.iter().enumerate() {
+            ecs.add_component(*entity, i as u32);
+        }
+        assert_eq!(ecs.query_changed::<u32>().count(), 4);
+
+        ecs.mark_components_clean::<u32>();
+        assert_eq!(ecs.query_changed::<u32>().count(), 0);
+
+        *ecs.get_component_mut::<u32>(entities[1]).unwrap() += 10;
+        ecs.add_component(entities[3], 30u32);
+        let _ = ecs.get_component::<u32>(entities[2]);
+
+        let changed: Vec<(Entity, u32)> = ecs.query_changed::<u32>().map(|(e, v)| (e, *v)).collect();
+        assert_eq!(changed, vec![(entities[1], 11), (entities[3], 30)]);
+        assert_eq!(ecs.query_changed::<i64>().count(), 0);
+    }
+
+    #[test]
+    fn test_world_stats() {
+        let mut ecs = ECS::new();
+        assert_eq!(ecs.world_stats(), WorldStats {
+            live_entities: 0,
+            dead_slots: 0,
+            component_types: 0,
+            approximate_bytes: 0
+        });
+
+        let entities = ecs.create_entities(4);
+        ecs.add_component(entities[1], 1u32);
+        ecs.add_component(entities[3], 3u32);
+        ecs.add_component(entities[0], 0u64);
+        ecs.destroy_entity(entities[2]);
+        ecs.destroy_entity(entities[3]);
+
+        let stats = ecs.world_stats();
+        assert_eq!(stats.live_entities, 2);
+        assert_eq!(stats.dead_slots, 2);
+        assert_eq!(stats.component_types, 2);
+        let u32_bytes = ecs.components::<u32>().unwrap().slot_count() * mem::size_of::<u32>();
+        let u64_bytes = ecs.components::<u64>().unwrap().slot_count() * mem::size_of::<u64>();
+        assert_eq!(stats.approximate_bytes, u32_bytes + u64_bytes);
+        assert!(stats.approximate_bytes > 0);
+    }
+
+    #[test]
+    fn test_component_counts() {
+        let mut ecs = ECS::new();
+        let entities = ecs.create_entities(3);
+        ecs.add_component(entities[0], 0u32);
+        ecs.add_component(entities[0], Counter(0));
+        ecs.add_component(entities[0], "first");
+        ecs.add_component(entities[1], 1u32);
+        ecs.add_component(entities[1], 10u32);
+
+        assert_eq!(ecs.component_count::<u32>(), 2);
+        assert_eq!(ecs.component_count::<Counter>(), 1);
+        assert_eq!(ecs.component_count::<i64>(), 0);
+        assert_eq!(ecs.entity_component_count(entities[0]), 3);
+        assert_eq!(ecs.entity_component_count(entities[1]), 1);
+        assert_eq!(ecs.entity_component_count(entities[2]), 0);
+
+        ecs.remove_component::<Counter>(entities[0]);
+        ecs.destroy_entity(entities[1]);
+        assert_eq!(ecs.component_count::<u32>(), 1);
+        assert_eq!(ecs.component_count::<Counter>(), 0);
+        assert_eq!(ecs.entity_component_count(entities[0]), 2);
+        assert_eq!(ecs.entity_component_count(entities[1]), 0);
+    }
+
+    #[test]
+    fn test_entities_with_component() {
+        let mut ecs = ECS::new();
+        let entities = ecs.create_entities(5);
+        for entity in entities.iter().step_by(2) {
+            ecs.add_component(*entity, Counter(0));
+        }
+
+        assert_eq!(ecs.entities_with_component::<Counter>(), vec![entities[0], entities[2], entities[4]]);
+        assert!(ecs.entities_with_component::<Gravity>().is_empty());
+
+        ecs.destroy_entity(entities[2]);
+        assert_eq!(ecs.entities_with_component::<Counter>(), vec![entities[0], entities[4]]);
+    }
+
+    #[test]
+    fn test_entities_with_all() {
+        let mut ecs = ECS::new();
+        let entities = ecs.create_entities(4);
+        ecs.add_component(entities[0], Counter(0));
+        ecs.add_component(entities[1], Counter(1));
+        ecs.add_component(entities[1], Gravity(1.0));
+        ecs.add_component(entities[2], Gravity(2.0));
+        ecs.add_component(entities[3], Counter(3));
+        ecs.add_component(entities[3], Gravity(3.0));
+
+        let types = [TypeId::of::<Counter>(), TypeId::of::<Gravity>()];
+        assert_eq!(ecs.entities_with_all(&types), vec![entities[1], entities[3]]);
+        assert_eq!(ecs.entities_with_all(&types[1..]), vec![entities[1], entities[2], entities[3]]);
+        assert_eq!(ecs.entities_with_all(&[]), entities);
+        assert!(ecs.entities_with_all(&[TypeId::of::<Counter>(), TypeId::of::<i64>()]).is_empty());
+
+        ecs.destroy_entity(entities[3]);
+        assert_eq!(ecs.entities_with_all(&types), vec![entities[1]]);
+    }
+
+    #[test]
+    fn test_take_component() {
+        let mut ecs = ECS::new();
+        let entity = ecs.spawn().with_component(Name("sword".to_string())).with_component(Counter(1)).build();
+
+        assert_eq!(ecs.entity_by_name("sword"), Some(entity));
+        assert_eq!(ecs.take_component::<Name>(entity), Some(Name("sword".to_string())));
+        assert_eq!(ecs.entity_by_name("sword"), None);
+        assert!(ecs.is_alive(entity));
+        assert!(!ecs.has_component::<Name>(entity));
+        assert!(ecs.has_component::<Counter>(entity));
+        assert_eq!(ecs.take_component::<Name>(entity), None);
+    }
+
+    #[test]
+    fn test_modify_component() {
+        let mut ecs = ECS::new();
+        let with_counter = ecs.spawn().with_component(Counter(1)).build();
+        let without_counter = ecs.create_entity();
+
+        assert!(ecs.modify_component::<Counter, _>(with_counter, |counter| counter.0 += 1));
+        assert_eq!(ecs.get_component::<Counter>(with_counter).map(|c| c.0), Some(2));
+
+        let mut called = false;
+        assert!(!ecs.modify_component::<Counter, _>(without_counter, |_| called = true));
+        assert!(!called);
+        assert!(!ecs.has_component::<Counter>(without_counter));
+    }
+
+    #[test]
+    fn test_add_or_replace_component() {
+        let mut ecs = ECS::new();
+        let entity = ecs.create_entity();
+
+        assert_eq!(ecs.add_or_replace_component(entity, Name("old".to_string())), None);
+        assert_eq!(ecs.add_or_replace_component(entity, Name("new".to_string())), Some(Name("old".to_string())));
+        assert_eq!(ecs.get_component::<Name>(entity), Some(&Name("new".to_string())));
+        assert_eq!(ecs.component_count::<Name>(), 1);
+        assert_eq!(ecs.entity_by_name("new"), Some(entity));
+        assert_eq!(ecs.entity_by_name("old"), None);
+    }
+
+    #[test]
+    fn test_swap_components() {
+        let mut ecs = ECS::new();
+        let a = ecs.spawn().with_component(Counter(1)).build();
+        let b = ecs.spawn().with_component(Counter(2)).build();
+        let c = ecs.create_entity();
+
+        assert!(ecs.swap_components::<Counter>(a, b));
+        assert_eq!(ecs.get_component::<Counter>(a).map(|c| c.0), Some(2));
+        assert_eq!(ecs.get_component::<Counter>(b).map(|c| c.0), Some(1));
+
+        // only one side has the component
+        assert!(!ecs.swap_components::<Counter>(a, c));
+        assert_eq!(ecs.get_component::<Counter>(a).map(|c| c.0), Some(2));
+        assert!(!ecs.has_component::<Counter>(c));
+
+        assert!(ecs.swap_components::<Counter>(a, a));
+        assert_eq!(ecs.get_component::<Counter>(a).map(|c| c.0), Some(2));
+    }
+
+    #[test]
+    fn test_move_component() {
+        let mut ecs = ECS::new();
+        let from = ecs.spawn().with_component(Name("inventory".to_string())).build();
+        let to = ecs.create_entity();
+        let dead = ecs.create_entity();
+        ecs.destroy_entity(dead);
+
+        assert!(!ecs.move_component::<Name>(from, dead));
+        assert!(ecs.has_component::<Name>(from));
+        assert!(!ecs.move_component::<Name>(to, from));
+        assert!(ecs.has_component::<Name>(from));
+
+        assert!(ecs.move_component::<Name>(from, to));
+        assert!(!ecs.has_component::<Name>(from));
+        assert_eq!(ecs.get_component::<Name>(to), Some(&Name("inventory".to_string())));
+        assert_eq!(ecs.entity_by_name("inventory"), Some(to));
+        assert!(ecs.is_alive(from));
+    }
+
+    #[test]
+    fn test_query_with_option() {
+        let mut ecs = ECS::new();
+        let both = ecs.spawn().with_component(Counter(1)).with_component(Gravity(1.0)).build();
+        let only_counter = ecs.spawn().with_component(Counter(2)).build();
+        let _only_gravity = ecs.spawn().with_component(Gravity(3.0)).build();
+
+        let results: Vec<(Entity, u32, Option<f32>)> = ecs.query_with_option::<Counter, Gravity>()
+            .map(|(entity, counter, gravity)| (entity, counter.0, gravity.map(|g| g.0)))
+            .collect();
+        assert_eq!(results, vec![(both, 1, Some(1.0)), (only_counter, 2, None)]);
+
+        // the optional type does not need to exist at all
+        assert_eq!(ecs.query_with_option::<Counter, Name>().filter(|(_, _, name)| name.is_none()).count(), 2);
+    }
+
+    #[test]
+    fn test_query_sorted() {
+        #[derive(Debug, PartialEq)]
+        struct Priority(i32);
+
+        let mut ecs = ECS::new();
+        let low = ecs.spawn().with_component(Priority(1)).build();
+        let high = ecs.spawn().with_component(Priority(10)).build();
+        let _unprioritized = ecs.create_entity();
+        let middle = ecs.spawn().with_component(Priority(5)).build();
+
+        let sorted = ecs.query_sorted::<Priority, _, _>(|priority| std::cmp::Reverse(priority.0));
+        assert_eq!(sorted, vec![(high, &Priority(10)), (middle, &Priority(5)), (low, &Priority(1))]);
+        assert!(ecs.query_sorted::<Counter, _, _>(|counter| counter.0).is_empty());
+    }
+
+    #[test]
+    fn test_retain_entities() {
+        let mut ecs = ECS::new();
+        let entities: Vec<Entity> = (0..5).map(|i| ecs.spawn().with_component(Counter(i)).build()).collect();
+        let without_counter = ecs.create_entity();
+
+        ecs.retain_entities(|ecs, entity| ecs.get_component::<Counter>(entity).is_none_or(|c| c.0 % 2 == 0));
+
+        assert_eq!(ecs.entity_count(), 4);
+        assert!(ecs.is_alive(without_counter));
+        for (i, entity) in entities.iter().enumerate() {
+            assert_eq!(ecs.is_alive(*entity), i % 2 == 0);
+        }
+        assert_eq!(ecs.get_component::<Counter>(entities[2]).map(|c| c.0), Some(2));
+        assert_eq!(ecs.component_count::<Counter>(), 3);
+    }
+
+    #[test]
+    fn test_retain_component() {
+        let mut ecs = ECS::new();
+        let expired = ecs.spawn().with_component(Counter(0)).with_component(Gravity(1.0)).build();
+        let only_counter = ecs.spawn().with_component(Counter(0)).build();
+        let active = ecs.spawn().with_component(Counter(3)).build();
+
+        ecs.retain_component::<Counter, _>(|_, counter| counter.0 > 0);
+
+        assert!(ecs.is_alive(expired));
+        assert!(ecs.is_alive(only_counter));
+        assert!(!ecs.has_component::<Counter>(expired));
+        assert!(!ecs.has_component::<Counter>(only_counter));
+        assert!(ecs.has_component::<Gravity>(expired));
+        assert_eq!(ecs.get_component::<Counter>(active).map(|c| c.0), Some(3));
+    }
+
+    #[test]
+    fn test_checkpoint_restore() {
+        let mut ecs = ECS::new();
+        ecs.register_cloneable_component::<Name>();
+        ecs.register_cloneable_component::<u32>();
+        let entities = ecs.create_entities(3);
+        ecs.add_component(entities[0], Name("first".to_string()));
+        ecs.add_component(entities[1], 1u32);
+        ecs.add_component(entities[2], Counter(2));
+        let snapshot = ecs.checkpoint();
+
+        ecs.get_component_mut::<Name>(entities[0]).unwrap().0.push_str(" renamed");
+        ecs.destroy_entity(entities[1]);
+        let added = ecs.spawn().with_component(5u32).build();
+        assert_eq!(added.index(), entities[1].index());
+
+        ecs.restore(snapshot);
+        assert_eq!(ecs.entity_count(), 3);
+        assert!(entities.iter().all(|entity| ecs.is_alive(*entity)));
+        assert!(!ecs.is_alive(added));
+        assert_eq!(ecs.get_component::<Name>(entities[0]), Some(&Name("first".to_string())));
+        assert_eq!(ecs.entity_by_name("first"), Some(entities[0]));
+        assert_eq!(ecs.get_component::<u32>(entities[1]), Some(&1));
+        assert_eq!(ecs.component_count::<u32>(), 1);
+        // not registered as cloneable
+        assert!(!ecs.has_component::<Counter>(entities[2]));
+
+        // the allocator continues where the snapshot left off
+        let next = ecs.create_entity();
+        assert_eq!(next.index(), 3);
+    }
+
+    #[test]
+    fn test_merge() {
+        let mut ecs = ECS::new();
+        let existing = ecs.spawn().with_component(Counter(1)).build();
+
+        let mut other = ECS::new();
+        let other_1 = other.spawn().with_component(Counter(10)).with_component(Gravity(1.0)).build();
+        let dead = other.create_entity();
+        let other_2 = other.spawn().with_component(Name("sublevel".to_string())).build();
+        other.destroy_entity(dead);
+
+        let mapping = ecs.merge(other);
+        assert_eq!(mapping.len(), 2);
+        assert!(!mapping.contains_key(&dead));
+        let merged_1 = mapping[&other_1];
+        let merged_2 = mapping[&other_2];
+
+        assert_eq!(ecs.entity_count(), 3);
+        assert_ne!(merged_1, existing);
+        assert_eq!(ecs.get_component::<Counter>(existing).map(|c| c.0), Some(1));
+        assert_eq!(ecs.get_component::<Counter>(merged_1).map(|c| c.0), Some(10));
+        assert_eq!(ecs.get_component::<Gravity>(merged_1).map(|g| g.0), Some(1.0));
+        assert_eq!(ecs.get_component::<Name>(merged_2), Some(&Name("sublevel".to_string())));
+        assert_eq!(ecs.entity_by_name("sublevel"), Some(merged_2));
+        assert_eq!(ecs.component_count::<Counter>(), 2);
+    }
+
+    #[test]
+    fn test_on_add_hook() {
+        let mut ecs = ECS::new();
+        // every Counter gets a Gravity next to it
+        ecs.register_on_add::<Counter, _>(|ecs, entity| ecs.add_component(entity, Gravity(9.81)));
+        // a hook adding its own type does not recurse
+        ecs.register_on_add::<u32, _>(|ecs, entity| {
+            let value = ecs.get_component::<u32>(entity).copied().unwrap();
+            let echo = ecs.create_entity();
+            ecs.add_component(echo, value + 1);
+        });
+
+        let entity = ecs.spawn().with_component(Counter(1)).build();
+        assert_eq!(ecs.get_component::<Gravity>(entity).map(|g| g.0), Some(9.81));
+
+        ecs.add_component(entity, Gravity(1.0));
+        ecs.add_component(entity, Counter(2));
+        // replacing does not call the hook again
+        assert_eq!(ecs.get_component::<Gravity>(entity).map(|g| g.0), Some(1.0));
+
+        ecs.add_component(entity, 1u32);
+        assert_eq!(ecs.component_count::<u32>(), 2);
+        assert_eq!(ecs.entity_count(), 2);
+    }
+
+    #[test]
+    fn test_on_remove_hook() {
+        let mut ecs = ECS::new();
+        let log = Rc::new(RefCell::new(Vec::new()));
+        let hook_log = log.clone();
+        ecs.register_on_remove::<Counter, _>(move |ecs, entity, counter| {
+            hook_log.borrow_mut().push((counter.0, ecs.is_alive(entity)));
+        });
+
+        let removed = ecs.spawn().with_component(Counter(1)).build();
+        let destroyed = ecs.spawn().with_component(Counter(2)).with_component(Gravity(1.0)).build();
+        let retained = ecs.spawn().with_component(Counter(3)).build();
+
+        assert_eq!(ecs.remove_component::<Counter>(removed).map(|c| c.0), Some(1));
+        assert!(ecs.destroy_entity(destroyed));
+        ecs.retain_component::<Counter, _>(|_, counter| counter.0 != 3);
+        ecs.swap_components::<Gravity>(removed, retained);
+        assert_eq!(*log.borrow(), vec![(1, true), (2, true), (3, true)]);
+        assert!(!ecs.has_component::<Counter>(retained));
+
+        let mut commands = Commands::new();
+        let entity = ecs.spawn().with_component(Counter(4)).build();
+        commands.remove_component::<Counter>(entity);
+        ecs.apply_commands(commands);
+        assert_eq!(log.borrow().last(), Some(&(4, true)));
+    }
+
+    #[test]
+    fn test_on_remove_hook_cascade() {
+        // points down a chain that loses its links together
+        struct Link(Option<Entity>);
+
+        let mut ecs = ECS::new();
+        let removed = Rc::new(RefCell::new(Vec::new()));
+        let hook_removed = removed.clone();
+        ecs.register_on_remove::<Link, _>(move |ecs, entity, link| {
+            hook_removed.borrow_mut().push(entity);
+            if let Some(next) = link.0 {
+                ecs.remove_component::<Link>(next);
+            }
+        });
+
+        let grandchild = ecs.spawn().with_component(Link(None)).build();
+        let child = ecs.spawn().with_component(Link(Some(grandchild))).build();
+        let root = ecs.spawn().with_component(Link(Some(child))).build();
+        ecs.remove_component::<Link>(root);
+
+        assert_eq!(*removed.borrow(), vec![root, child, grandchild]);
+        assert_eq!(ecs.component_count::<Link>(), 0);
+    }
+
+    #[test]
+    fn test_on_destroy_hook() {
+        struct Alive(u32);
+
+        let mut ecs = ECS::new();
+        let order = Rc::new(RefCell::new(Vec::new()));
+        ecs.insert_resource(Alive(3));
+        let hook_order = order.clone();
+        ecs.register_on_destroy(move |ecs, entity| {
+            assert!(!ecs.is_alive(entity));
+            assert_eq!(ecs.entity_component_count(entity), 0);
+            ecs.get_resource_mut::<Alive>().unwrap().0 -= 1;
+            hook_order.borrow_mut().push("first");
+        });
+        let hook_order = order.clone();
+        ecs.register_on_destroy(move |_, _| hook_order.borrow_mut().push("second"));
+
+        let entities: Vec<Entity> = (0..3).map(|i| ecs.spawn().with_component(Counter(i)).build()).collect();
+        ecs.destroy_entity(entities[0]);
+        ecs.destroy_entity(entities[0]);
+        assert_eq!(ecs.get_resource::<Alive>().map(|a| a.0), Some(2));
+
+        ecs.retain_entities(|_, _| false);
+        assert_eq!(ecs.get_resource::<Alive>().map(|a| a.0), Some(0));
+        assert_eq!(*order.borrow(), ["first", "second"].repeat(3));
+    }
+
+    #[test]
+    fn test_debug() {
+        let mut ecs = ECS::new();
+        let entity = ecs.spawn().with_component(Counter(1)).with_component(5u32).build();
+        ecs.create_entity();
+        ecs.insert_resource(Gravity(9.81));
+
+        let output = format!("{:?}", ecs);
+        assert!(output.starts_with("ECS { live_entities: 2, component_types: [\"recs::ecs::tests::Counter\", \"u32\"], resources: 1"));
+        assert!(output.contains(&format!("{:?}: [\"recs::ecs::tests::Counter\", \"u32\"]", entity)));
+
+        ecs.create_entities(DEBUG_ENTITY_LIMIT);
+        let output = format!("{:?}", ecs);
+        assert!(output.contains("live_entities: 66"));
+        assert!(!output.contains("entities: {"));
+    }
+
+    #[test]
+    fn test_component_infos() {
+        let mut ecs = ECS::new();
+        ecs.register_component::<Counter>();
+        ecs.register_component::<u32>();
+        ecs.register_component::<Counter>();
+        let counter_info = |ecs: &ECS| ecs.component_infos()[0];
+        assert_eq!(ecs.component_infos().len(), 2);
+        assert_eq!(counter_info(&ecs), ComponentStorageInfo {
+            type_name: "recs::ecs::tests::Counter",
+            type_id: TypeId::of::<Counter>(),
+            count: 0
+        });
+
+        let entities: Vec<Entity> = (0..3).map(|i| ecs.spawn().with_component(Counter(i)).build()).collect();
+        ecs.add_component(entities[0], 0u32);
+        // adding registers the type, after the explicitly registered ones
+        ecs.add_component(entities[0], Gravity(1.0));
+        assert_eq!(counter_info(&ecs).count, 3);
+        assert_eq!(ecs.component_infos()[1].count, 1);
+        assert_eq!(ecs.component_infos().len(), 3);
+        assert_eq!(ecs.component_infos()[2].type_id, TypeId::of::<Gravity>());
+
+        ecs.remove_component::<Counter>(entities[1]);
+        ecs.destroy_entity(entities[2]);
+        assert_eq!(counter_info(&ecs).count, 1);
+    }
+
+    #[test]
+    fn test_component_count_of_registered_type() {
+        let mut ecs = ECS::new();
+        ecs.register_component::<Counter>();
+        assert_eq!(ecs.component_count::<Counter>(), 0);
+        assert!(!ecs.any_with::<Counter>());
+        assert_eq!(ecs.world_stats().component_types, 1);
+
+        let entity = ecs.create_entity();
+        ecs.add_component(entity, Counter(1));
+        assert_eq!(ecs.component_count::<Counter>(), 1);
+        assert_eq!(ecs.component_infos().len(), 1);
+    }
+
+    #[test]
+    fn test_query_first() {
+        let mut ecs = ECS::new();
+        assert!(ecs.query_first::<Counter>().is_none());
+
+        ecs.create_entity();
+        let player = ecs.spawn().with_component(Counter(7)).build();
+        let (entity, counter) = ecs.query_first::<Counter>().unwrap();
+        assert_eq!(entity, player);
+        assert_eq!(counter.0, 7);
+
+        ecs.destroy_entity(player);
+        assert!(ecs.query_first::<Counter>().is_none());
+    }
+
+    #[test]
+    fn test_try_single() {
+        let mut ecs = ECS::new();
+        assert_eq!(ecs.try_single::<Counter>().err(), Some(SingleError::NoEntities));
+        assert_eq!(ecs.try_single_mut::<Counter>().err(), Some(SingleError::NoEntities));
+
+        let camera = ecs.spawn().with_component(Counter(1)).build();
+        assert_eq!(ecs.try_single::<Counter>().map(|c| c.0), Ok(1));
+        ecs.single_mut::<Counter>().0 += 1;
+        assert_eq!(ecs.single::<Counter>().0, 2);
+
+        ecs.spawn().with_component(Counter(3)).build();
+        assert_eq!(ecs.try_single::<Counter>().err(), Some(SingleError::MultipleEntities));
+        assert_eq!(ecs.try_single_mut::<Counter>().err(), Some(SingleError::MultipleEntities));
+
+        ecs.destroy_entity(camera);
+        assert_eq!(ecs.single::<Counter>().0, 3);
+    }
+
+    #[test]
+    fn test_get_two_components_mut_failure_changes_nothing() {
+        let mut ecs = ECS::new();
+        let entity = ecs.spawn().with_component(Counter(1)).build();
+        ecs.spawn().with_component(Gravity(1.0)).build();
+        ecs.mark_components_clean::<Counter>();
+
+        assert!(ecs.get_two_components_mut::<Counter, Gravity>(entity).is_none());
+        assert_eq!(ecs.query_changed::<Counter>().count(), 0);
+    }
+
+    #[test]
+    fn test_try_single_mut_failure_changes_nothing() {
+        let mut ecs = ECS::new();
+        ecs.spawn().with_component(Counter(1)).build();
+        ecs.spawn().with_component(Counter(2)).build();
+        ecs.mark_components_clean::<Counter>();
+
+        assert!(ecs.try_single_mut::<Counter>().is_err());
+        assert_eq!(ecs.query_changed::<Counter>().count(), 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "no entity has the component")]
+    fn test_single_none() {
+        let ecs = ECS::new();
+        ecs.single::<Counter>();
+    }
+
+    #[test]
+    #[should_panic(expected = "more than one entity has the component")]
+    fn test_single_multiple() {
+        let mut ecs = ECS::new();
+        ecs.spawn().with_component(Counter(1)).build();
+        ecs.spawn().with_component(Counter(2)).build();
+        ecs.single::<Counter>();
+    }
+
+    #[test]
+    fn test_query_count() {
+        let mut ecs = ECS::new();
+        assert_eq!(ecs.query_count::<Counter>(), 0);
+
+        let entities: Vec<Entity> = (0..4).map(|i| ecs.spawn().with_component(Counter(i)).build()).collect();
+        ecs.add_component(entities[0], Counter(10));
+        assert_eq!(ecs.query_count::<Counter>(), 4);
+
+        ecs.remove_component::<Counter>(entities[1]);
+        ecs.remove_component::<Counter>(entities[1]);
+        ecs.destroy_entity(entities[2]);
+        assert_eq!(ecs.query_count::<Counter>(), 2);
+        assert_eq!(ecs.query_count::<Counter>(), ecs.query::<Counter>().into_iter().count());
+
+        // a stale slot does not count twice when it is reused
+        let successor = ecs.create_entity();
+        ecs.add_component(successor, Counter(5));
+        assert_eq!(ecs.query_count::<Counter>(), 3);
+    }
+
+    #[test]
+    fn test_any_with() {
+        let mut ecs = ECS::new();
+        assert!(!ecs.any_with::<Counter>());
+
+        let entity = ecs.spawn().with_component(Counter(1)).build();
+        assert!(ecs.any_with::<Counter>());
+        assert!(!ecs.any_with::<Gravity>());
+
+        ecs.destroy_entity(entity);
+        assert!(!ecs.any_with::<Counter>());
+    }
+
+    #[test]
+    fn test_get_two_components_mut() {
+        let mut ecs = ECS::new();
+        let entity = ecs.spawn().with_component(Counter(1)).with_component(Gravity(2.0)).build();
+        let only_counter = ecs.spawn().with_component(Counter(3)).build();
+
+        let (counter, gravity) = ecs.get_two_components_mut::<Counter, Gravity>(entity).unwrap();
+        counter.0 += gravity.0 as u32;
+        gravity.0 = 0.0;
+        assert_eq!(ecs.get_component::<Counter>(entity).map(|c| c.0), Some(3));
+        assert_eq!(ecs.get_component::<Gravity>(entity).map(|g| g.0), Some(0.0));
+
+        assert!(ecs.get_two_components_mut::<Counter, Gravity>(only_counter).is_none());
+        assert!(ecs.get_two_components_mut::<Counter, Name>(entity).is_none());
+    }
+
+    #[test]
+    #[should_panic(expected = "get_two_components_mut needs distinct component types")]
+    fn test_get_two_components_mut_same_type() {
+        let mut ecs = ECS::new();
+        let entity = ecs.spawn().with_component(Counter(1)).build();
+        ecs.get_two_components_mut::<Counter, Counter>(entity);
+    }
+
+    #[test]
+    fn test_query_tagged() {
+        struct IsEnemy;
+
+        let mut ecs = ECS::new();
+        let enemy_1 = ecs.spawn().with_component(IsEnemy).with_component(Counter(1)).build();
+        let _friend = ecs.spawn().with_component(Counter(2)).build();
+        let enemy_2 = ecs.spawn().with_component(IsEnemy).build();
+
+        assert_eq!(ecs.query_tagged::<IsEnemy>().collect::<Vec<Entity>>(), vec![enemy_1, enemy_2]);
+        let enemy_counters: Vec<u32> = ecs.query_tagged::<IsEnemy>()
+            .filter_map(|entity| ecs.get_component::<Counter>(entity))
+            .map(|counter| counter.0)
+            .collect();
+        assert_eq!(enemy_counters, vec![1]);
+        assert_eq!(ecs.query_tagged::<Gravity>().count(), 0);
+    }
+
+    #[test]
+    fn test_clear() {
+        let mut ecs = ECS::new();
+        ecs.insert_resource(Gravity(9.81));
+        let shared = Rc::new(());
+        for i in 0..1000 {
+            let entity = ecs.spawn().with_component(Counter(i)).with_component(shared.clone()).build();
+            if i % 3 == 0 {
+                ecs.destroy_entity(entity);
+            }
+        }
+        assert_eq!(Rc::strong_count(&shared), 667);
+
+        ecs.clear();
+        assert_eq!(ecs.entity_count(), 0);
+        assert_eq!(ecs.entities().count(), 0);
+        assert_eq!(ecs.query::<Counter>().into_iter().count(), 0);
+        assert_eq!(ecs.query_count::<Rc<()>>(), 0);
+        // every component was dropped
+        assert_eq!(Rc::strong_count(&shared), 1);
+        assert!(ecs.get_resource::<Gravity>().is_some());
+
+        let entity = ecs.spawn().with_component(Counter(1)).build();
+        assert_eq!(ecs.query_count::<Counter>(), 1);
+        assert_eq!(ecs.get_component::<Counter>(entity).map(|c| c.0), Some(1));
+    }
+
+    #[test]
+    fn test_clear_components_only() {
+        let mut ecs = ECS::new();
+        let entities: Vec<Entity> = (0..10).map(|i| ecs.spawn().with_component(Counter(i)).with_component(Gravity(1.0)).build()).collect();
+
+        ecs.clear_components_only();
+        assert_eq!(ecs.entity_count(), 10);
+        for entity in entities.iter() {
+            assert!(ecs.is_alive(*entity));
+            assert!(!ecs.has_component::<Counter>(*entity));
+            assert!(!ecs.has_component::<Gravity>(*entity));
+            assert_eq!(ecs.entity_component_count(*entity), 0);
+        }
+
+        ecs.add_component(entities[4], Counter(4));
+        assert_eq!(ecs.get_component::<Counter>(entities[4]).map(|c| c.0), Some(4));
+    }
+}
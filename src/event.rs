@@ -0,0 +1,86 @@
+/// Double buffered queue of events of type `T`.
+///
+/// Events sent during one tick become readable after the next `update` and
+/// are dropped by the update after that. Readers thus never see events sent
+/// in the same tick, which prevents systems from feeding each other events
+/// in an endless loop.
+pub struct Events<T> {
+    // events sent during the previous tick, visible to readers
+    previous: Vec<T>,
+    // events sent during the current tick
+    current: Vec<T>,
+}
+
+impl<T> Events<T> {
+    pub fn new() -> Events<T> {
+        Events {
+            previous: Vec::new(),
+            current: Vec::new()
+        }
+    }
+
+    pub fn send(&mut self, event: T) {
+        self.current.push(event);
+    }
+
+    /// Iterates over the events sent during the previous tick.
+    pub fn read(&self) -> std::slice::Iter<'_, T> {
+        self.previous.iter()
+    }
+
+    /// Ends the current tick by swapping the buffers.
+    pub fn update(&mut self) {
+        self.previous.clear();
+        std::mem::swap(&mut self.previous, &mut self.current);
+    }
+}
+
+impl<T> Default for Events<T> {
+    fn default() -> Events<T> {
+        Events::new()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ecs::ECS;
+
+    #[test]
+    fn test_events_buffering() {
+        let mut events = Events::new();
+        events.send(1);
+        assert_eq!(events.read().count(), 0);
+
+        events.update();
+        events.send(2);
+        assert_eq!(events.read().collect::<Vec<&i32>>(), vec![&1]);
+
+        events.update();
+        assert_eq!(events.read().collect::<Vec<&i32>>(), vec![&2]);
+
+        events.update();
+        assert_eq!(events.read().count(), 0);
+    }
+
+    #[test]
+    fn test_ecs_events() {
+        let mut ecs = ECS::new();
+        assert_eq!(ecs.read_events::<&str>().count(), 0);
+
+        // tick N
+        ecs.send_event("hit");
+        ecs.send_event("miss");
+        assert_eq!(ecs.read_events::<&str>().count(), 0);
+        ecs.update_events();
+
+        // tick N + 1
+        assert_eq!(ecs.read_events::<&str>().collect::<Vec<&&str>>(), vec![&"hit", &"miss"]);
+        ecs.update_events();
+
+        // tick N + 2
+        assert_eq!(ecs.read_events::<&str>().count(), 0);
+        assert!(ecs.get_resource::<Events<&str>>().is_some());
+    }
+}
@@ -0,0 +1,239 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::ecs::{Entity, ECS};
+
+/// Points from a child to its parent. Maintained by `ECS::set_parent`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Parent(pub Entity);
+
+/// Lists the children of an entity. Maintained by `ECS::set_parent`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Children(pub Vec<Entity>);
+
+impl ECS {
+    /// Makes `child` a child of `parent`, detaching it from any previous
+    /// parent. Returns `false` and changes nothing if either entity is dead
+    /// or the link would make the hierarchy cyclic.
+    pub fn set_parent(&mut self, child: Entity, parent: Entity) -> bool {
+        if !self.is_alive(child) || !self.is_alive(parent) || self.is_ancestor(child, parent) {
+            return false;
+        }
+
+        self.remove_parent(child);
+        self.add_component(child, Parent(parent));
+        match self.get_component_mut::<Children>(parent) {
+            Some(children) => children.0.push(child),
+            None => self.add_component(parent, Children(vec![child]))
+        }
+        true
+    }
+
+    /// Detaches `child` from its parent and returns the former parent.
+    pub fn remove_parent(&mut self, child: Entity) -> Option<Entity> {
+        let Parent(parent) = self.remove_component::<Parent>(child)?;
+        let now_childless = match self.get_component_mut::<Children>(parent) {
+            Some(children) => {
+                children.0.retain(|entity| *entity != child);
+                children.0.is_empty()
+            },
+            None => false
+        };
+        if now_childless {
+            self.remove_component::<Children>(parent);
+        }
+        Some(parent)
+    }
+
+    /// Destroys the entity together with all of its descendants. Returns
+    /// `false` if the entity was not alive.
+    pub fn despawn_recursive(&mut self, entity: Entity) -> bool {
+        if !self.is_alive(entity) {
+            return false;
+        }
+
+        // collect first, destroying detaches the links being followed; the
+        // visited set guards against cycles in a corrupted hierarchy
+        let mut visited = HashSet::new();
+        let mut pending = vec![entity];
+        let mut descendants = Vec::new();
+        while let Some(current) = pending.pop() {
+            if !visited.insert(current) {
+                continue;
+            }
+            descendants.push(current);
+            if let Some(children) = self.get_component::<Children>(current) {
+                pending.extend(children.0.iter().copied());
+            }
+        }
+
+        // children before their parents
+        for entity in descendants.into_iter().rev() {
+            self.destroy_entity(entity);
+        }
+        true
+    }
+
+    // points the hierarchy links of merged entities at their new handles
+    pub(crate) fn remap_hierarchy(&mut self, mapping: &HashMap<Entity, Entity>) {
+        for entity in mapping.values() {
+            if let Some(parent) = self.get_component_mut::<Parent>(*entity) {
+                if let Some(new_parent) = mapping.get(&parent.0) {
+                    parent.0 = *new_parent;
+                }
+            }
+            if let Some(children) = self.get_component_mut::<Children>(*entity) {
+                for child in children.0.iter_mut() {
+                    if let Some(new_child) = mapping.get(child) {
+                        *child = *new_child;
+                    }
+                }
+            }
+        }
+    }
+
+    // whether `ancestor` is `entity` itself or one of its ancestors
+    fn is_ancestor(&self, ancestor: Entity, entity: Entity) -> bool {
+        let mut current = Some(entity);
+        while let Some(entity) = current {
+            if entity == ancestor {
+                return true;
+            }
+            current = self.get_component::<Parent>(entity).map(|parent| parent.0);
+        }
+        false
+    }
+
+    // unlinks the entity from its parent and orphans its children, so no
+    // link points to it once it is destroyed
+    pub(crate) fn detach_from_hierarchy(&mut self, entity: Entity) {
+        self.remove_parent(entity);
+        if let Some(Children(children)) = self.remove_component::<Children>(entity) {
+            for child in children {
+                self.remove_component::<Parent>(child);
+            }
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn children_of(ecs: &ECS, entity: Entity) -> Vec<Entity> {
+        ecs.get_component::<Children>(entity).map(|children| children.0.clone()).unwrap_or_default()
+    }
+
+    #[test]
+    fn test_three_levels() {
+        let mut ecs = ECS::new();
+        let root = ecs.create_entity();
+        let middle = ecs.create_entity();
+        let leaf_1 = ecs.create_entity();
+        let leaf_2 = ecs.create_entity();
+
+        assert!(ecs.set_parent(middle, root));
+        assert!(ecs.set_parent(leaf_1, middle));
+        assert!(ecs.set_parent(leaf_2, middle));
+
+        assert_eq!(ecs.get_component::<Parent>(middle), Some(&Parent(root)));
+        assert_eq!(ecs.get_component::<Parent>(leaf_2), Some(&Parent(middle)));
+        assert_eq!(children_of(&ecs, root), vec![middle]);
+        assert_eq!(children_of(&ecs, middle), vec![leaf_1, leaf_2]);
+
+        // cycles are refused
+        assert!(!ecs.set_parent(root, leaf_1));
+        assert!(!ecs.set_parent(middle, middle));
+        assert_eq!(ecs.get_component::<Parent>(root), None);
+    }
+
+    #[test]
+    fn test_reparenting() {
+        let mut ecs = ECS::new();
+        let root = ecs.create_entity();
+        let branch_1 = ecs.create_entity();
+        let branch_2 = ecs.create_entity();
+        let leaf = ecs.create_entity();
+        ecs.set_parent(branch_1, root);
+        ecs.set_parent(branch_2, root);
+        ecs.set_parent(leaf, branch_1);
+
+        assert!(ecs.set_parent(leaf, branch_2));
+        assert_eq!(ecs.get_component::<Parent>(leaf), Some(&Parent(branch_2)));
+        assert!(!ecs.has_component::<Children>(branch_1));
+        assert_eq!(children_of(&ecs, branch_2), vec![leaf]);
+
+        assert_eq!(ecs.remove_parent(leaf), Some(branch_2));
+        assert_eq!(ecs.remove_parent(leaf), None);
+        assert!(!ecs.has_component::<Children>(branch_2));
+    }
+
+    #[test]
+    fn test_destroy_unlinks() {
+        let mut ecs = ECS::new();
+        let root = ecs.create_entity();
+        let middle = ecs.create_entity();
+        let leaf = ecs.create_entity();
+        ecs.set_parent(middle, root);
+        ecs.set_parent(leaf, middle);
+
+        ecs.destroy_entity(middle);
+        assert!(!ecs.has_component::<Children>(root));
+        assert!(!ecs.has_component::<Parent>(leaf));
+        assert!(ecs.is_alive(leaf));
+    }
+
+    #[test]
+    fn test_despawn_recursive() {
+        let mut ecs = ECS::new();
+        let root = ecs.create_entity();
+        let middle = ecs.create_entity();
+        let leaf_1 = ecs.create_entity();
+        let leaf_2 = ecs.create_entity();
+        let sibling = ecs.create_entity();
+        ecs.set_parent(middle, root);
+        ecs.set_parent(sibling, root);
+        ecs.set_parent(leaf_1, middle);
+        ecs.set_parent(leaf_2, middle);
+
+        assert!(ecs.despawn_recursive(middle));
+        for entity in [middle, leaf_1, leaf_2].iter() {
+            assert!(!ecs.is_alive(*entity));
+        }
+        assert_eq!(children_of(&ecs, root), vec![sibling]);
+
+        assert!(ecs.despawn_recursive(root));
+        assert_eq!(ecs.entity_count(), 0);
+        assert!(!ecs.despawn_recursive(root));
+    }
+
+    #[test]
+    fn test_despawn_recursive_cycle() {
+        let mut ecs = ECS::new();
+        let a = ecs.create_entity();
+        let b = ecs.create_entity();
+        // set_parent refuses cycles, so build one by hand
+        ecs.add_component(a, Children(vec![b]));
+        ecs.add_component(b, Children(vec![a]));
+
+        assert!(ecs.despawn_recursive(a));
+        assert!(!ecs.is_alive(a));
+        assert!(!ecs.is_alive(b));
+    }
+
+    #[test]
+    fn test_merge_remaps_hierarchy() {
+        let mut ecs = ECS::new();
+        ecs.create_entities(2);
+
+        let mut other = ECS::new();
+        let root = other.create_entity();
+        let leaf = other.create_entity();
+        other.set_parent(leaf, root);
+
+        let mapping = ecs.merge(other);
+        let (root, leaf) = (mapping[&root], mapping[&leaf]);
+        assert_eq!(ecs.get_component::<Parent>(leaf), Some(&Parent(root)));
+        assert_eq!(children_of(&ecs, root), vec![leaf]);
+    }
+}
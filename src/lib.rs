@@ -1 +1,15 @@
-pub mod allocation;
\ No newline at end of file
+pub mod allocation;
+pub mod archetype;
+pub mod bitset;
+pub mod bundle;
+pub mod commands;
+pub mod ecs;
+pub mod event;
+pub mod hierarchy;
+pub mod name;
+pub mod query;
+pub mod relation;
+pub mod storage;
+pub mod system;
+pub mod time;
+pub mod world;
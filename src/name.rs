@@ -0,0 +1,219 @@
+use std::any::Any;
+use std::collections::HashMap;
+
+use crate::ecs::{Entity, ECS};
+
+/// Human readable name of an entity, found by `ECS::entity_by_name`. Can
+/// be added like any component or via `ECS::set_name`; changing it in
+/// place through `get_component_mut` hides it from the lookup.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Name(pub String);
+
+// reverse index from name to the entities carrying it, named last at the
+// end; stored as a resource and updated on every change of a Name
+#[derive(Default)]
+struct NameIndex(HashMap<String, Vec<Entity>>);
+
+// the name held by the component, if it is a Name
+pub(crate) fn name_of(component: &dyn Any) -> Option<String> {
+    component.downcast_ref::<Name>().map(|name| name.0.clone())
+}
+
+impl ECS {
+    /// Names the entity, replacing any previous name. Returns `false` if
+    /// the entity is dead. Same as adding a `Name` component. Names are
+    /// meant to be unique; if several entities share one,
+    /// `entity_by_name` finds the one named last.
+    pub fn set_name(&mut self, entity: Entity, name: &str) -> bool {
+        if !self.is_alive(entity) {
+            return false;
+        }
+        self.add_component(entity, Name(name.to_string()));
+        true
+    }
+
+    /// Removes the entity's name and returns it.
+    pub fn remove_name(&mut self, entity: Entity) -> Option<Name> {
+        self.remove_component::<Name>(entity)
+    }
+
+    /// Finds the live entity with the given name.
+    pub fn entity_by_name(&self, name: &str) -> Option<Entity> {
+        let entities = self.get_resource::<NameIndex>()?.0.get(name)?;
+        // a Name changed in place via get_component_mut is not tracked
+        entities.iter().rev().copied().find(|entity| match self.get_component::<Name>(*entity) {
+            Some(Name(current)) => current == name,
+            None => false
+        })
+    }
+
+    // moves the entity from the old to the new name in the index; called
+    // for every added, replaced or removed component
+    pub(crate) fn name_changed(&mut self, entity: Entity, old: Option<String>, new: Option<String>) {
+        if old.is_none() && new.is_none() {
+            return;
+        }
+        if self.get_resource::<NameIndex>().is_none() {
+            self.insert_resource(NameIndex::default());
+        }
+        // inserted above if it was missing
+        let index = &mut self.get_resource_mut::<NameIndex>().unwrap().0;
+        if let Some(old) = old {
+            if let Some(entities) = index.get_mut(&old) {
+                entities.retain(|named| *named != entity);
+                if entities.is_empty() {
+                    index.remove(&old);
+                }
+            }
+        }
+        if let Some(new) = new {
+            index.entry(new).or_default().push(entity);
+        }
+    }
+
+    // recreates the index from the Name components, after storages were
+    // cleared or replaced without going through name_changed
+    pub(crate) fn rebuild_name_index(&mut self) {
+        let mut index = NameIndex::default();
+        for (entity, name) in self.query::<Name>() {
+            index.0.entry(name.0.clone()).or_default().push(entity);
+        }
+        if index.0.is_empty() {
+            self.remove_resource::<NameIndex>();
+        } else {
+            self.insert_resource(index);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::Commands;
+
+    #[test]
+    fn test_entity_by_name() {
+        let mut ecs = ECS::new();
+        let player = ecs.create_entity();
+        let enemy = ecs.create_entity();
+        assert_eq!(ecs.entity_by_name("player"), None);
+
+        assert!(ecs.set_name(player, "player"));
+        assert!(ecs.set_name(enemy, "enemy"));
+        assert_eq!(ecs.entity_by_name("player"), Some(player));
+        assert_eq!(ecs.entity_by_name("enemy"), Some(enemy));
+        assert_eq!(ecs.get_component::<Name>(player), Some(&Name("player".to_string())));
+        assert_eq!(ecs.entity_by_name("nobody"), None);
+    }
+
+    #[test]
+    fn test_rename() {
+        let mut ecs = ECS::new();
+        let entity = ecs.create_entity();
+        ecs.set_name(entity, "old");
+        ecs.set_name(entity, "new");
+
+        assert_eq!(ecs.entity_by_name("old"), None);
+        assert_eq!(ecs.entity_by_name("new"), Some(entity));
+
+        assert_eq!(ecs.remove_name(entity), Some(Name("new".to_string())));
+        assert_eq!(ecs.entity_by_name("new"), None);
+        assert_eq!(ecs.remove_name(entity), None);
+    }
+
+    #[test]
+    fn test_name_of_destroyed_entity() {
+        let mut ecs = ECS::new();
+        let entity = ecs.create_entity();
+        ecs.set_name(entity, "doomed");
+        ecs.destroy_entity(entity);
+
+        assert_eq!(ecs.entity_by_name("doomed"), None);
+        assert!(!ecs.set_name(entity, "doomed"));
+
+        // the reused slot must not inherit the name
+        let successor = ecs.create_entity();
+        assert_eq!(ecs.entity_by_name("doomed"), None);
+        ecs.set_name(successor, "doomed");
+        assert_eq!(ecs.entity_by_name("doomed"), Some(successor));
+    }
+
+    fn name(name: &str) -> Name {
+        Name(name.to_string())
+    }
+
+    #[test]
+    fn test_name_component_changes() {
+        let mut ecs = ECS::new();
+        let entity = ecs.create_entity();
+        ecs.add_component(entity, name("added"));
+        assert_eq!(ecs.entity_by_name("added"), Some(entity));
+
+        ecs.add_component(entity, name("replaced"));
+        assert_eq!(ecs.entity_by_name("added"), None);
+        assert_eq!(ecs.entity_by_name("replaced"), Some(entity));
+
+        ecs.add_or_replace_component(entity, name("swapped in"));
+        assert_eq!(ecs.entity_by_name("swapped in"), Some(entity));
+        assert_eq!(ecs.entity_by_name("replaced"), None);
+
+        let other = ecs.spawn().with_component(name("other")).build();
+        ecs.swap_components::<Name>(entity, other);
+        assert_eq!(ecs.entity_by_name("other"), Some(entity));
+        assert_eq!(ecs.entity_by_name("swapped in"), Some(other));
+
+        ecs.remove_component::<Name>(entity);
+        assert_eq!(ecs.entity_by_name("other"), None);
+
+        let mut commands = Commands::new();
+        commands.remove_component::<Name>(other);
+        ecs.apply_commands(commands);
+        assert_eq!(ecs.entity_by_name("swapped in"), None);
+    }
+
+    #[test]
+    fn test_shared_name() {
+        let mut ecs = ECS::new();
+        let a = ecs.create_entity();
+        let b = ecs.create_entity();
+        ecs.set_name(a, "n");
+        ecs.set_name(b, "n");
+        assert_eq!(ecs.entity_by_name("n"), Some(b));
+
+        // the earlier holder is found again once the later one is renamed
+        ecs.set_name(b, "m");
+        assert_eq!(ecs.entity_by_name("n"), Some(a));
+        ecs.set_name(b, "n");
+        ecs.destroy_entity(b);
+        assert_eq!(ecs.entity_by_name("n"), Some(a));
+    }
+
+    #[test]
+    fn test_names_after_bulk_changes() {
+        let mut ecs = ECS::new();
+        ecs.register_cloneable_component::<Name>();
+        let entity = ecs.create_entity();
+        ecs.set_name(entity, "x");
+        let snapshot = ecs.checkpoint();
+
+        let copy = ecs.clone_entity(entity).unwrap();
+        ecs.set_name(entity, "y");
+        assert_eq!(ecs.entity_by_name("x"), Some(copy));
+
+        ecs.restore(snapshot);
+        assert_eq!(ecs.entity_by_name("x"), Some(entity));
+        assert_eq!(ecs.entity_by_name("y"), None);
+
+        let mut other = ECS::new();
+        let merged = other.create_entity();
+        other.set_name(merged, "merged");
+        let mapping = ecs.merge(other);
+        assert_eq!(ecs.entity_by_name("merged"), Some(mapping[&merged]));
+
+        ecs.clear_components_only();
+        assert_eq!(ecs.entity_by_name("x"), None);
+        ecs.set_name(entity, "x");
+        ecs.clear();
+        assert_eq!(ecs.entity_by_name("x"), None);
+    }
+}
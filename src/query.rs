@@ -0,0 +1,508 @@
+use std::any::TypeId;
+
+use crate::allocation::{Iter, IterMut, SlotsMut};
+use crate::bitset;
+use crate::ecs::{ComponentStorage, Entity, EntityMap, ECS};
+
+/// Read-only view on all entities that have a component of type `T`.
+pub struct Query<'a, T> {
+    ecs: &'a ECS,
+    components: Option<&'a EntityMap<T>>,
+    excluded: Vec<&'a dyn ComponentStorage>,
+}
+
+impl<'a, T: 'static> Query<'a, T> {
+    pub(crate) fn new(ecs: &'a ECS) -> Query<'a, T> {
+        Query {
+            ecs,
+            components: ecs.components::<T>(),
+            excluded: Vec::new()
+        }
+    }
+
+    /// Skips every entity that also has a component of type `E`, e.g.
+    /// `ecs.query::<Enemy>().without::<Stunned>()`.
+    pub fn without<E: 'static>(mut self) -> Query<'a, T> {
+        // a type that was never added cannot exclude anything
+        if let Some(storage) = self.ecs.storage(TypeId::of::<E>()) {
+            self.excluded.push(storage);
+        }
+        self
+    }
+}
+
+impl<'a, T> IntoIterator for Query<'a, T> {
+    type Item = (Entity, &'a T);
+    type IntoIter = QueryIter<'a, T>;
+
+    fn into_iter(self) -> QueryIter<'a, T> {
+        QueryIter {
+            inner: self.components.map(|components| components.iter()),
+            excluded: self.excluded
+        }
+    }
+}
+
+pub struct QueryIter<'a, T> {
+    // None if no component of type T was ever added
+    inner: Option<Iter<'a, T>>,
+    excluded: Vec<&'a dyn ComponentStorage>,
+}
+
+impl<'a, T> Iterator for QueryIter<'a, T> {
+    type Item = (Entity, &'a T);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let excluded = &self.excluded;
+        self.inner.as_mut()?
+            .find(|(entity, _)| !excluded.iter().any(|storage| storage.contains_entity(*entity)))
+    }
+}
+
+/// Mutable view on all entities that have a component of type `T`.
+pub struct QueryMut<'a, T> {
+    components: Option<&'a mut EntityMap<T>>,
+}
+
+impl<'a, T> QueryMut<'a, T> {
+    pub(crate) fn new(components: Option<&'a mut EntityMap<T>>) -> QueryMut<'a, T> {
+        QueryMut {
+            components
+        }
+    }
+}
+
+impl<'a, T> IntoIterator for QueryMut<'a, T> {
+    type Item = (Entity, &'a mut T);
+    type IntoIter = QueryIterMut<'a, T>;
+
+    fn into_iter(self) -> QueryIterMut<'a, T> {
+        QueryIterMut {
+            inner: self.components.map(|components| components.iter_mut())
+        }
+    }
+}
+
+pub struct QueryIterMut<'a, T> {
+    inner: Option<IterMut<'a, T>>,
+}
+
+impl<'a, T> Iterator for QueryIterMut<'a, T> {
+    type Item = (Entity, &'a mut T);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.inner.as_mut()?.next()
+    }
+}
+
+/// Read-only view on all entities that have both an `A` and a `B`.
+///
+/// Intersects the occupied slots of both storages and only looks up the
+/// slots found in both.
+pub struct Query2<'a, A, B> {
+    a: Option<&'a EntityMap<A>>,
+    b: Option<&'a EntityMap<B>>,
+}
+
+impl<'a, A, B> Query2<'a, A, B> {
+    pub(crate) fn new(a: Option<&'a EntityMap<A>>, b: Option<&'a EntityMap<B>>) -> Query2<'a, A, B> {
+        Query2 {
+            a,
+            b
+        }
+    }
+}
+
+impl<'a, A, B> IntoIterator for Query2<'a, A, B> {
+    type Item = (Entity, &'a A, &'a B);
+    type IntoIter = Query2Iter<'a, A, B>;
+
+    fn into_iter(self) -> Query2Iter<'a, A, B> {
+        let join = match (self.a, self.b) {
+            (Some(a), Some(b)) => {
+                let slots = a.occupied().intersection(b.occupied());
+                Some((slots.into_iter(), a, b))
+            },
+            _ => None
+        };
+        Query2Iter {
+            join
+        }
+    }
+}
+
+pub struct Query2Iter<'a, A, B> {
+    // None if one of the component types was never added
+    join: Option<(bitset::IntoIter, &'a EntityMap<A>, &'a EntityMap<B>)>,
+}
+
+impl<'a, A, B> Iterator for Query2Iter<'a, A, B> {
+    type Item = (Entity, &'a A, &'a B);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (slots, a, b) = self.join.as_mut()?;
+        for slot in slots {
+            if let (Some((a_entity, a)), Some((b_entity, b))) = (a.get_slot(slot), b.get_slot(slot)) {
+                // same slot but different generations cannot be joined
+                if a_entity == b_entity {
+                    return Some((a_entity, a, b));
+                }
+            }
+        }
+        None
+    }
+}
+
+/// Mutable view on all entities that have both an `A` and a `B`.
+///
+/// Only the joined components are marked as changed; components of
+/// entities that lack the other type are left alone.
+pub struct Query2Mut<'a, A, B> {
+    a: Option<&'a mut EntityMap<A>>,
+    b: Option<&'a mut EntityMap<B>>,
+}
+
+impl<'a, A, B> Query2Mut<'a, A, B> {
+    pub(crate) fn new(a: Option<&'a mut EntityMap<A>>, b: Option<&'a mut EntityMap<B>>) -> Query2Mut<'a, A, B> {
+        Query2Mut {
+            a,
+            b
+        }
+    }
+}
+
+impl<'a, A, B> IntoIterator for Query2Mut<'a, A, B> {
+    type Item = (Entity, &'a mut A, &'a mut B);
+    type IntoIter = Query2IterMut<'a, A, B>;
+
+    fn into_iter(self) -> Query2IterMut<'a, A, B> {
+        let join = match (self.a, self.b) {
+            (Some(a), Some(b)) => {
+                let slots = a.occupied().intersection(b.occupied());
+                Some((slots.into_iter(), a.slots_mut(), b.slots_mut()))
+            },
+            _ => None
+        };
+        Query2IterMut {
+            join
+        }
+    }
+}
+
+pub struct Query2IterMut<'a, A, B> {
+    // None if one of the component types was never added
+    join: Option<(bitset::IntoIter, SlotsMut<'a, A>, SlotsMut<'a, B>)>,
+}
+
+impl<'a, A, B> Iterator for Query2IterMut<'a, A, B> {
+    type Item = (Entity, &'a mut A, &'a mut B);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (slots, a_slots, b_slots) = self.join.as_mut()?;
+        for slot in slots {
+            if let (Some((a_entity, a, a_dirty)), Some((b_entity, b, b_dirty))) = (a_slots.take(slot), b_slots.take(slot)) {
+                // same slot but different generations cannot be joined
+                if a_entity == b_entity {
+                    *a_dirty = true;
+                    *b_dirty = true;
+                    return Some((a_entity, a, b));
+                }
+            }
+        }
+        None
+    }
+}
+
+/// Read-only view on all entities that have an `A`, a `B` and a `C`.
+///
+/// Intersects the occupied slots of all three storages and only looks up
+/// the slots found in every one of them.
+pub struct Query3<'a, A, B, C> {
+    a: Option<&'a EntityMap<A>>,
+    b: Option<&'a EntityMap<B>>,
+    c: Option<&'a EntityMap<C>>,
+}
+
+impl<'a, A, B, C> Query3<'a, A, B, C> {
+    pub(crate) fn new(
+        a: Option<&'a EntityMap<A>>,
+        b: Option<&'a EntityMap<B>>,
+        c: Option<&'a EntityMap<C>>
+    ) -> Query3<'a, A, B, C> {
+        Query3 {
+            a,
+            b,
+            c
+        }
+    }
+}
+
+impl<'a, A, B, C> IntoIterator for Query3<'a, A, B, C> {
+    type Item = (Entity, &'a A, &'a B, &'a C);
+    type IntoIter = Query3Iter<'a, A, B, C>;
+
+    fn into_iter(self) -> Query3Iter<'a, A, B, C> {
+        let join = match (self.a, self.b, self.c) {
+            (Some(a), Some(b), Some(c)) => {
+                let slots = a.occupied().intersection(b.occupied()).intersection(c.occupied());
+                Some((slots.into_iter(), a, b, c))
+            },
+            _ => None
+        };
+        Query3Iter {
+            join
+        }
+    }
+}
+
+type Join3<'a, A, B, C> = (bitset::IntoIter, &'a EntityMap<A>, &'a EntityMap<B>, &'a EntityMap<C>);
+
+pub struct Query3Iter<'a, A, B, C> {
+    join: Option<Join3<'a, A, B, C>>,
+}
+
+impl<'a, A, B, C> Iterator for Query3Iter<'a, A, B, C> {
+    type Item = (Entity, &'a A, &'a B, &'a C);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (slots, a, b, c) = self.join.as_mut()?;
+        for slot in slots {
+            if let (Some((entity, a)), Some(b), Some(c)) = (a.get_slot(slot), b.get_slot(slot), c.get_slot(slot)) {
+                if b.0 == entity && c.0 == entity {
+                    return Some((entity, a, b.1, c.1));
+                }
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ecs::{Entity, ECS};
+
+    #[test]
+    fn test_query() {
+        let mut ecs = ECS::new();
+        let mut expected = Vec::new();
+        for i in 0..1000u32 {
+            let entity = ecs.create_entity();
+            if i % 2 == 0 {
+                ecs.add_component(entity, i);
+                expected.push((entity, i));
+            } else {
+                ecs.add_component(entity, "odd");
+            }
+        }
+
+        let found: Vec<(Entity, u32)> = ecs.query::<u32>()
+            .into_iter()
+            .map(|(entity, value)| (entity, *value))
+            .collect();
+        assert_eq!(found.len(), 500);
+        assert_eq!(found, expected);
+    }
+
+    #[test]
+    fn test_query_unknown_type() {
+        let mut ecs = ECS::new();
+        ecs.create_entity();
+        assert_eq!(ecs.query::<u32>().into_iter().count(), 0);
+    }
+
+    #[test]
+    fn test_query_mut() {
+        let mut ecs = ECS::new();
+        let mut entities = Vec::new();
+        for i in 0..10u32 {
+            let entity = ecs.create_entity();
+            ecs.add_component(entity, i);
+            entities.push(entity);
+        }
+
+        for (_, value) in ecs.query_mut::<u32>() {
+            *value *= 2;
+        }
+
+        let values: Vec<u32> = ecs.query::<u32>().into_iter().map(|(_, value)| *value).collect();
+        assert_eq!(values, (0..10).map(|i| i * 2).collect::<Vec<u32>>());
+        assert_eq!(ecs.get_component::<u32>(entities[3]), Some(&6));
+        assert_eq!(ecs.query_mut::<i64>().into_iter().count(), 0);
+    }
+
+    #[test]
+    fn test_query2() {
+        let mut ecs = ECS::new();
+        let mut both = Vec::new();
+        for i in 0..30u32 {
+            let entity = ecs.create_entity();
+            match i % 3 {
+                0 => ecs.add_component(entity, i),
+                1 => ecs.add_component(entity, i as i64),
+                _ => {
+                    ecs.add_component(entity, i);
+                    ecs.add_component(entity, i as i64);
+                    both.push(entity);
+                }
+            }
+        }
+
+        let joined: Vec<(Entity, u32, i64)> = ecs.query2::<u32, i64>()
+            .into_iter()
+            .map(|(entity, a, b)| (entity, *a, *b))
+            .collect();
+        assert_eq!(joined.len(), both.len());
+        for (entity, a, b) in joined {
+            assert!(both.contains(&entity));
+            assert_eq!(a as i64, b);
+        }
+
+        // the join is the same whichever side drives
+        let mut reversed: Vec<Entity> = ecs.query2::<i64, u32>().into_iter().map(|(e, _, _)| e).collect();
+        reversed.sort_by_key(|entity| entity.index());
+        assert_eq!(reversed, both);
+
+        assert_eq!(ecs.query2::<u32, &str>().into_iter().count(), 0);
+    }
+
+    #[test]
+    fn test_query2_mut() {
+        let mut ecs = ECS::new();
+        let mut entities = Vec::new();
+        for i in 0..20u32 {
+            let entity = ecs.create_entity();
+            if i % 2 == 0 {
+                ecs.add_component(entity, i);
+            }
+            if i % 3 == 0 {
+                ecs.add_component(entity, i as i64);
+            }
+            entities.push(entity);
+        }
+
+        let mut visited = 0;
+        for (_, a, b) in ecs.query2_mut::<u32, i64>() {
+            *a += 100;
+            *b = -*b;
+            visited += 1;
+        }
+        assert_eq!(visited, 4);
+
+        assert_eq!(ecs.get_component::<u32>(entities[6]), Some(&106));
+        assert_eq!(ecs.get_component::<i64>(entities[6]), Some(&-6));
+        // entities outside the join are untouched
+        assert_eq!(ecs.get_component::<u32>(entities[2]), Some(&2));
+        assert_eq!(ecs.get_component::<i64>(entities[3]), Some(&3));
+    }
+
+    #[test]
+    fn test_query2_mut_marks_only_joined_changed() {
+        let mut ecs = ECS::new();
+        let only_a = ecs.spawn().with_component(1u32).build();
+        let both = ecs.spawn().with_component(2u32).with_component(2i64).build();
+        let only_b = ecs.spawn().with_component(3i64).build();
+        ecs.mark_components_clean::<u32>();
+        ecs.mark_components_clean::<i64>();
+
+        for _ in ecs.query2_mut::<u32, i64>() {}
+        let changed_a: Vec<Entity> = ecs.query_changed::<u32>().map(|(entity, _)| entity).collect();
+        let changed_b: Vec<Entity> = ecs.query_changed::<i64>().map(|(entity, _)| entity).collect();
+        assert_eq!(changed_a, vec![both]);
+        assert_eq!(changed_b, vec![both]);
+
+        ecs.destroy_entity(both);
+        ecs.mark_components_clean::<u32>();
+        ecs.mark_components_clean::<i64>();
+        assert_eq!(ecs.query2_mut::<u32, i64>().into_iter().count(), 0);
+        assert_eq!(ecs.query_changed::<u32>().count(), 0);
+        assert_eq!(ecs.query_changed::<i64>().count(), 0);
+        assert!(ecs.has_component::<u32>(only_a) && ecs.has_component::<i64>(only_b));
+    }
+
+    #[test]
+    #[should_panic(expected = "distinct component types")]
+    fn test_query2_mut_same_type() {
+        let mut ecs = ECS::new();
+        ecs.query2_mut::<u32, u32>();
+    }
+
+    #[derive(Debug, PartialEq)]
+    struct Position(i32);
+    #[derive(Debug, PartialEq)]
+    struct Velocity(i32);
+    #[derive(Debug, PartialEq)]
+    struct Health(u32);
+
+    #[test]
+    fn test_query3() {
+        let mut ecs = ECS::new();
+        let mut expected = Vec::new();
+        for i in 0..40 {
+            let entity = ecs.create_entity();
+            if i % 2 == 0 {
+                ecs.add_component(entity, Position(i));
+            }
+            if i % 4 == 0 {
+                ecs.add_component(entity, Velocity(i));
+            }
+            if i % 5 == 0 {
+                ecs.add_component(entity, Health(i as u32));
+            }
+            if i % 20 == 0 {
+                expected.push(entity);
+            }
+        }
+
+        let found: Vec<Entity> = ecs.query3::<Position, Velocity, Health>()
+            .into_iter()
+            .map(|(entity, position, velocity, health)| {
+                assert_eq!(position.0, velocity.0);
+                assert_eq!(position.0 as u32, health.0);
+                entity
+            })
+            .collect();
+        assert_eq!(found, expected);
+
+        // the order of the type parameters does not change the result
+        assert_eq!(ecs.query3::<Health, Position, Velocity>().into_iter().count(), 2);
+        assert_eq!(ecs.query3::<Velocity, Health, Position>().into_iter().count(), 2);
+        assert_eq!(ecs.query3::<Position, Velocity, u8>().into_iter().count(), 0);
+    }
+
+    struct Enemy;
+    struct Stunned;
+    struct Frozen;
+
+    #[test]
+    fn test_query_without() {
+        let mut ecs = ECS::new();
+        let mut active = Vec::new();
+        for i in 0..12 {
+            let entity = ecs.create_entity();
+            ecs.add_component(entity, Enemy);
+            if i % 2 == 0 {
+                ecs.add_component(entity, Stunned);
+            }
+            if i % 3 == 0 {
+                ecs.add_component(entity, Frozen);
+            }
+            if i % 2 != 0 && i % 3 != 0 {
+                active.push(entity);
+            }
+        }
+
+        assert_eq!(ecs.query::<Enemy>().without::<Stunned>().into_iter().count(), 6);
+        assert_eq!(ecs.query::<Enemy>().without::<Frozen>().into_iter().count(), 8);
+
+        let found: Vec<Entity> = ecs.query::<Enemy>()
+            .without::<Stunned>()
+            .without::<Frozen>()
+            .into_iter()
+            .map(|(entity, _)| entity)
+            .collect();
+        assert_eq!(found, active);
+
+        // excluding an unknown type filters nothing
+        assert_eq!(ecs.query::<Enemy>().without::<u8>().into_iter().count(), 12);
+    }
+}
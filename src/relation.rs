@@ -0,0 +1,303 @@
+use std::collections::HashMap;
+use std::marker::PhantomData;
+
+use crate::ecs::{Entity, ECS};
+
+// objects the entity relates to via R, stored as a component of the subject
+struct Targets<R> {
+    entities: Vec<Entity>,
+    relation: PhantomData<fn() -> R>,
+}
+
+// subjects relating to the entity via R, stored as a component of the object
+struct Sources<R> {
+    entities: Vec<Entity>,
+    relation: PhantomData<fn() -> R>,
+}
+
+// implemented by hand, since deriving would require R: Clone
+impl<R> Clone for Targets<R> {
+    fn clone(&self) -> Targets<R> {
+        Targets {
+            entities: self.entities.clone(),
+            relation: PhantomData
+        }
+    }
+}
+
+impl<R> Clone for Sources<R> {
+    fn clone(&self) -> Sources<R> {
+        Sources {
+            entities: self.entities.clone(),
+            relation: PhantomData
+        }
+    }
+}
+
+// resource marking that the cleanup hooks for R are registered
+struct RelationHooks<R>(PhantomData<fn() -> R>);
+
+// points the edges of one relation type at the new handles of merged
+// entities and sets up the relation in the receiving world
+type RelationRemapper = fn(&mut ECS, &HashMap<Entity, Entity>);
+
+// resource with a remapper for each relation type used in the world
+struct RelationTypes(Vec<RelationRemapper>);
+
+impl ECS {
+    /// Adds a directed edge of relation type `R` from `subject` to
+    /// `object`, e.g. `ecs.relate::<Follows>(follower, leader)`. Returns
+    /// `false` if either entity is dead or the edge already exists. Edges
+    /// are removed when either end is destroyed.
+    pub fn relate<R: 'static>(&mut self, subject: Entity, object: Entity) -> bool {
+        if !self.is_alive(subject) || !self.is_alive(object) || self.related::<R>(subject).contains(&object) {
+            return false;
+        }
+        self.register_relation_hooks::<R>();
+
+        match self.get_component_mut::<Targets<R>>(subject) {
+            Some(targets) => targets.entities.push(object),
+            None => self.add_component(subject, Targets::<R> {
+                entities: vec![object],
+                relation: PhantomData
+            })
+        }
+        match self.get_component_mut::<Sources<R>>(object) {
+            Some(sources) => sources.entities.push(subject),
+            None => self.add_component(object, Sources::<R> {
+                entities: vec![subject],
+                relation: PhantomData
+            })
+        }
+        true
+    }
+
+    /// Removes the edge of relation type `R` from `subject` to `object`.
+    /// Returns `false` if there was none.
+    pub fn unrelate<R: 'static>(&mut self, subject: Entity, object: Entity) -> bool {
+        if !self.related::<R>(subject).contains(&object) {
+            return false;
+        }
+        self.remove_target::<R>(subject, object);
+        self.remove_source::<R>(object, subject);
+        true
+    }
+
+    /// The objects `entity` relates to via `R`, in the order they were
+    /// related.
+    pub fn related<R: 'static>(&self, entity: Entity) -> &[Entity] {
+        match self.get_component::<Targets<R>>(entity) {
+            Some(targets) => &targets.entities,
+            None => &[]
+        }
+    }
+
+    /// The subjects that relate to `entity` via `R`.
+    pub fn related_by<R: 'static>(&self, entity: Entity) -> &[Entity] {
+        match self.get_component::<Sources<R>>(entity) {
+            Some(sources) => &sources.entities,
+            None => &[]
+        }
+    }
+
+    fn remove_target<R: 'static>(&mut self, subject: Entity, object: Entity) {
+        let now_empty = match self.get_component_mut::<Targets<R>>(subject) {
+            Some(targets) => {
+                targets.entities.retain(|entity| *entity != object);
+                targets.entities.is_empty()
+            },
+            None => false
+        };
+        if now_empty {
+            self.remove_component::<Targets<R>>(subject);
+        }
+    }
+
+    fn remove_source<R: 'static>(&mut self, object: Entity, subject: Entity) {
+        let now_empty = match self.get_component_mut::<Sources<R>>(object) {
+            Some(sources) => {
+                sources.entities.retain(|entity| *entity != subject);
+                sources.entities.is_empty()
+            },
+            None => false
+        };
+        if now_empty {
+            self.remove_component::<Sources<R>>(object);
+        }
+    }
+
+    // patches the relations that came along with the entities of `other`,
+    // see `ECS::merge`
+    pub(crate) fn remap_relations(&mut self, other: &ECS, mapping: &HashMap<Entity, Entity>) {
+        let remappers = match other.get_resource::<RelationTypes>() {
+            Some(relation_types) => relation_types.0.clone(),
+            None => return
+        };
+        for remap in remappers {
+            remap(self, mapping);
+        }
+    }
+
+    fn remap_relation<R: 'static>(&mut self, mapping: &HashMap<Entity, Entity>) {
+        self.register_relation_hooks::<R>();
+        for entity in mapping.values() {
+            if let Some(targets) = self.get_component_mut::<Targets<R>>(*entity) {
+                remap_entities(&mut targets.entities, mapping);
+            }
+            if let Some(sources) = self.get_component_mut::<Sources<R>>(*entity) {
+                remap_entities(&mut sources.entities, mapping);
+            }
+        }
+    }
+
+    // keeps the other end of each edge in sync when an entity is destroyed,
+    // and lets `checkpoint` capture the edges
+    fn register_relation_hooks<R: 'static>(&mut self) {
+        if self.get_resource::<RelationHooks<R>>().is_some() {
+            return;
+        }
+        self.insert_resource(RelationHooks::<R>(PhantomData));
+        // not cloneable for clone_entity, that would copy only one end of
+        // each edge
+        self.register_storage_cloner::<Targets<R>>();
+        self.register_storage_cloner::<Sources<R>>();
+        let remapper: RelationRemapper = |ecs, mapping| ecs.remap_relation::<R>(mapping);
+        match self.get_resource_mut::<RelationTypes>() {
+            Some(relation_types) => relation_types.0.push(remapper),
+            None => self.insert_resource(RelationTypes(vec![remapper]))
+        }
+        self.register_on_remove::<Targets<R>, _>(|ecs, subject, targets| {
+            for object in targets.entities.iter() {
+                ecs.remove_source::<R>(*object, subject);
+            }
+        });
+        self.register_on_remove::<Sources<R>, _>(|ecs, object, sources| {
+            for subject in sources.entities.iter() {
+                ecs.remove_target::<R>(*subject, object);
+            }
+        });
+    }
+}
+
+fn remap_entities(entities: &mut [Entity], mapping: &HashMap<Entity, Entity>) {
+    for entity in entities.iter_mut() {
+        if let Some(new_entity) = mapping.get(entity) {
+            *entity = *new_entity;
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Follows;
+    struct Likes;
+
+    #[test]
+    fn test_relate() {
+        let mut ecs = ECS::new();
+        let leader = ecs.create_entity();
+        let follower_1 = ecs.create_entity();
+        let follower_2 = ecs.create_entity();
+
+        assert!(ecs.relate::<Follows>(follower_1, leader));
+        assert!(ecs.relate::<Follows>(follower_2, leader));
+        assert!(ecs.relate::<Follows>(follower_2, follower_1));
+        assert!(!ecs.relate::<Follows>(follower_1, leader));
+        assert!(ecs.relate::<Likes>(leader, follower_2));
+
+        assert_eq!(ecs.related::<Follows>(follower_2), &[leader, follower_1]);
+        assert_eq!(ecs.related_by::<Follows>(leader), &[follower_1, follower_2]);
+        assert_eq!(ecs.related_by::<Follows>(follower_1), &[follower_2]);
+        assert!(ecs.related::<Follows>(leader).is_empty());
+        // relation types are independent
+        assert_eq!(ecs.related::<Likes>(leader), &[follower_2]);
+        assert!(ecs.related::<Likes>(follower_2).is_empty());
+    }
+
+    #[test]
+    fn test_unrelate() {
+        let mut ecs = ECS::new();
+        let leader = ecs.create_entity();
+        let follower = ecs.create_entity();
+        ecs.relate::<Follows>(follower, leader);
+
+        assert!(!ecs.unrelate::<Follows>(leader, follower));
+        assert!(ecs.unrelate::<Follows>(follower, leader));
+        assert!(!ecs.unrelate::<Follows>(follower, leader));
+        assert!(ecs.related::<Follows>(follower).is_empty());
+        assert!(ecs.related_by::<Follows>(leader).is_empty());
+        assert_eq!(ecs.entity_component_count(follower), 0);
+        assert_eq!(ecs.entity_component_count(leader), 0);
+    }
+
+    #[test]
+    fn test_destroy_removes_edges() {
+        let mut ecs = ECS::new();
+        let leader = ecs.create_entity();
+        let follower_1 = ecs.create_entity();
+        let follower_2 = ecs.create_entity();
+        ecs.relate::<Follows>(follower_1, leader);
+        ecs.relate::<Follows>(follower_2, leader);
+        ecs.relate::<Follows>(leader, follower_2);
+
+        ecs.destroy_entity(follower_1);
+        assert_eq!(ecs.related_by::<Follows>(leader), &[follower_2]);
+
+        ecs.destroy_entity(leader);
+        assert!(ecs.related::<Follows>(follower_2).is_empty());
+        assert!(ecs.related_by::<Follows>(follower_2).is_empty());
+        assert!(!ecs.relate::<Follows>(follower_2, leader));
+    }
+
+    #[test]
+    fn test_merge_remaps_edges() {
+        let mut ecs = ECS::new();
+        let bystanders = ecs.create_entities(4);
+
+        let mut other = ECS::new();
+        let leader = other.create_entity();
+        let follower = other.create_entity();
+        other.relate::<Follows>(follower, leader);
+        let mapping = ecs.merge(other);
+        let (leader, follower) = (mapping[&leader], mapping[&follower]);
+
+        assert_eq!(ecs.related::<Follows>(follower), &[leader]);
+        assert_eq!(ecs.related_by::<Follows>(leader), &[follower]);
+        for bystander in bystanders {
+            assert!(ecs.related_by::<Follows>(bystander).is_empty());
+        }
+
+        // the cleanup hooks came along
+        ecs.destroy_entity(leader);
+        assert!(ecs.related::<Follows>(follower).is_empty());
+        assert_eq!(ecs.entity_component_count(follower), 0);
+    }
+
+    #[test]
+    fn test_checkpoint_keeps_edges() {
+        let mut ecs = ECS::new();
+        let leader = ecs.create_entity();
+        let follower = ecs.create_entity();
+        ecs.relate::<Follows>(follower, leader);
+        let snapshot = ecs.checkpoint();
+
+        ecs.unrelate::<Follows>(follower, leader);
+        ecs.restore(snapshot);
+        assert_eq!(ecs.related::<Follows>(follower), &[leader]);
+        assert_eq!(ecs.related_by::<Follows>(leader), &[follower]);
+
+        // the cleanup hooks still apply to the restored edges
+        ecs.destroy_entity(leader);
+        assert!(ecs.related::<Follows>(follower).is_empty());
+
+        // copies of an entity do not take part in its relations
+        let other = ecs.create_entity();
+        ecs.relate::<Follows>(follower, other);
+        let copy = ecs.clone_entity(follower).unwrap();
+        assert!(ecs.related::<Follows>(copy).is_empty());
+        assert_eq!(ecs.related_by::<Follows>(other), &[follower]);
+    }
+}
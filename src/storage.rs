@@ -0,0 +1,220 @@
+use crate::allocation::GenerationalIndex;
+
+/// Component storage that keeps its values packed together.
+///
+/// Compared to `GenerationalIndexArray<T>`, iterating touches only the
+/// `n_occupied` stored values instead of all `n_slots` slots, which pays off
+/// when few entities carry the component. The price is one extra level of
+/// indirection on lookup and an additional `Option<usize>` per slot plus one
+/// `GenerationalIndex` per stored value. Removal swaps the last value into
+/// the freed position, so iteration order changes when values are removed.
+pub struct SparseSet<T> {
+    // slot index -> position in `dense`
+    sparse: Vec<Option<usize>>,
+    dense: Vec<T>,
+    // the full index of each value in `dense`
+    indices: Vec<GenerationalIndex>,
+}
+
+/// Packed component storage: values live in one contiguous `Vec`, a sparse
+/// lookup maps slots to positions and removal swap-removes. This is exactly
+/// the layout of `SparseSet`, so the two names refer to the same type.
+///
+/// It is a standalone container. The `ECS` always stores components in an
+/// `EntityMap<T>`, and it cannot be told to use this storage instead,
+/// because queries and change tracking rely on the slots, the occupied
+/// bitset and the dirty flags of `GenerationalIndexArray`.
+pub type DenseComponentStorage<T> = SparseSet<T>;
+
+impl<T> SparseSet<T> {
+    pub fn new() -> SparseSet<T> {
+        SparseSet {
+            sparse: Vec::new(),
+            dense: Vec::new(),
+            indices: Vec::new()
+        }
+    }
+
+    pub fn set(&mut self, index: GenerationalIndex, value: T) {
+        let inx = index.index();
+        if self.sparse.len() <= inx {
+            self.sparse.resize(inx + 1, None);
+        }
+
+        match self.sparse[inx] {
+            Some(position) => {
+                self.dense[position] = value;
+                self.indices[position] = index;
+            },
+            None => {
+                self.sparse[inx] = Some(self.dense.len());
+                self.dense.push(value);
+                self.indices.push(index);
+            }
+        }
+    }
+
+    pub fn get(&self, index: GenerationalIndex) -> Option<&T> {
+        let position = self.position(index)?;
+        Some(&self.dense[position])
+    }
+
+    pub fn get_mut(&mut self, index: GenerationalIndex) -> Option<&mut T> {
+        let position = self.position(index)?;
+        Some(&mut self.dense[position])
+    }
+
+    pub fn contains(&self, index: GenerationalIndex) -> bool {
+        self.position(index).is_some()
+    }
+
+    pub fn remove(&mut self, index: GenerationalIndex) -> Option<T> {
+        let position = self.position(index)?;
+        self.sparse[index.index()] = None;
+
+        // the last value moves into the freed position
+        let value = self.dense.swap_remove(position);
+        self.indices.swap_remove(position);
+        if let Some(moved) = self.indices.get(position) {
+            self.sparse[moved.index()] = Some(position);
+        }
+        Some(value)
+    }
+
+    /// Number of stored values.
+    pub fn len(&self) -> usize {
+        self.dense.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.dense.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (GenerationalIndex, &T)> + '_ {
+        self.indices.iter().copied().zip(self.dense.iter())
+    }
+
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = (GenerationalIndex, &mut T)> + '_ {
+        self.indices.iter().copied().zip(self.dense.iter_mut())
+    }
+
+    fn position(&self, index: GenerationalIndex) -> Option<usize> {
+        let position = (*self.sparse.get(index.index())?)?;
+        if self.indices[position] == index {
+            Some(position)
+        } else {
+            None
+        }
+    }
+}
+
+impl<T> Default for SparseSet<T> {
+    fn default() -> SparseSet<T> {
+        SparseSet::new()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::allocation::GenerationalIndexAllocator;
+
+    #[test]
+    fn test_set_get() {
+        let mut allocator = GenerationalIndexAllocator::new();
+        let mut set = SparseSet::new();
+        let index_1 = allocator.allocate();
+        let index_2 = allocator.allocate();
+
+        set.set(index_2, "two");
+        assert_eq!(set.get(index_1), None);
+        assert_eq!(set.get(index_2), Some(&"two"));
+
+        set.set(index_2, "zwei");
+        assert_eq!(set.get(index_2), Some(&"zwei"));
+        assert_eq!(set.iter().count(), 1);
+
+        if let Some(value) = set.get_mut(index_2) {
+            *value = "deux";
+        }
+        assert_eq!(set.get(index_2), Some(&"deux"));
+    }
+
+    #[test]
+    fn test_stale_generation() {
+        let mut allocator = GenerationalIndexAllocator::new();
+        let mut set = SparseSet::new();
+        let old_index = allocator.allocate();
+        set.set(old_index, 1);
+        allocator.deallocate(old_index);
+        let new_index = allocator.allocate();
+
+        assert!(set.contains(old_index));
+        assert!(!set.contains(new_index));
+        assert_eq!(set.remove(new_index), None);
+
+        set.set(new_index, 2);
+        assert!(!set.contains(old_index));
+        assert_eq!(set.get(new_index), Some(&2));
+    }
+
+    #[test]
+    fn test_remove_keeps_others_reachable() {
+        let mut allocator = GenerationalIndexAllocator::new();
+        let mut set = SparseSet::new();
+        let indices: Vec<GenerationalIndex> = (0..5).map(|_| allocator.allocate()).collect();
+        for (value, index) in indices.iter().enumerate() {
+            set.set(*index, value);
+        }
+
+        assert_eq!(set.remove(indices[1]), Some(1));
+        assert_eq!(set.remove(indices[1]), None);
+        assert_eq!(set.remove(indices[4]), Some(4));
+
+        assert!(!set.contains(indices[1]));
+        for value in [0, 2, 3].iter() {
+            assert_eq!(set.get(indices[*value]), Some(value));
+        }
+        assert_eq!(set.iter().count(), 3);
+    }
+
+    #[test]
+    fn test_iter_mut() {
+        let mut allocator = GenerationalIndexAllocator::new();
+        let mut set = SparseSet::new();
+        let index_1 = allocator.allocate();
+        let _index_2 = allocator.allocate();
+        let index_3 = allocator.allocate();
+        set.set(index_1, 1);
+        set.set(index_3, 3);
+
+        for (_, value) in set.iter_mut() {
+            *value *= 2;
+        }
+
+        let mut entries: Vec<(GenerationalIndex, &i32)> = set.iter().collect();
+        entries.sort_by_key(|(index, _)| index.index());
+        assert_eq!(entries, vec![(index_1, &2), (index_3, &6)]);
+    }
+
+    #[test]
+    fn test_dense_storage_stays_packed() {
+        let mut allocator = GenerationalIndexAllocator::new();
+        let mut storage: DenseComponentStorage<u32> = DenseComponentStorage::new();
+        let indices = allocator.allocate_many(1000);
+
+        // spread out indices still occupy only as many values as stored
+        storage.set(indices[999], 999);
+        storage.set(indices[500], 500);
+        storage.set(indices[0], 0);
+        assert_eq!(storage.len(), 3);
+        assert!(storage.dense.capacity() < 1000);
+
+        assert_eq!(storage.remove(indices[500]), Some(500));
+        assert_eq!(storage.len(), 2);
+        assert_eq!(storage.get(indices[999]), Some(&999));
+        assert_eq!(storage.get(indices[0]), Some(&0));
+        assert!(!storage.is_empty());
+    }
+}
@@ -0,0 +1,280 @@
+use std::any::type_name;
+use std::marker::PhantomData;
+use std::ops::{Deref, DerefMut};
+
+use crate::commands::Commands;
+use crate::ecs::{System, ECS};
+use crate::query::Query;
+
+/// A value a function system can take as a parameter, see `IntoSystem`.
+///
+/// Fetching happens in three steps: `take` moves whatever the parameter
+/// needs exclusive access to out of the world, `get` builds the parameter
+/// from the world and the taken state, and `restore` puts the state back
+/// after the system has run.
+pub trait SystemParam {
+    type State;
+    type Item<'w>;
+
+    fn take(ecs: &mut ECS) -> Self::State;
+    fn get<'w>(ecs: &'w ECS, state: &'w mut Self::State) -> Self::Item<'w>;
+    fn restore(ecs: &mut ECS, state: Self::State);
+}
+
+/// Shared access to the resource of type `T`.
+pub struct Res<'a, T> {
+    value: &'a T,
+}
+
+impl<'a, T> Deref for Res<'a, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        self.value
+    }
+}
+
+/// Exclusive access to the resource of type `T`.
+pub struct ResMut<'a, T> {
+    value: &'a mut T,
+}
+
+impl<'a, T> Deref for ResMut<'a, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        self.value
+    }
+}
+
+impl<'a, T> DerefMut for ResMut<'a, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        self.value
+    }
+}
+
+impl<'a, T: 'static> SystemParam for Query<'a, T> {
+    type State = ();
+    type Item<'w> = Query<'w, T>;
+
+    fn take(_ecs: &mut ECS) {}
+
+    fn get<'w>(ecs: &'w ECS, _state: &'w mut ()) -> Query<'w, T> {
+        ecs.query::<T>()
+    }
+
+    fn restore(_ecs: &mut ECS, _state: ()) {}
+}
+
+impl<'a, T: 'static> SystemParam for Res<'a, T> {
+    type State = ();
+    type Item<'w> = Res<'w, T>;
+
+    fn take(_ecs: &mut ECS) {}
+
+    fn get<'w>(ecs: &'w ECS, _state: &'w mut ()) -> Res<'w, T> {
+        match ecs.get_resource::<T>() {
+            Some(value) => Res {
+                value
+            },
+            None => panic!("resource {} is missing or borrowed mutably", type_name::<T>())
+        }
+    }
+
+    fn restore(_ecs: &mut ECS, _state: ()) {}
+}
+
+impl<'a, T: 'static> SystemParam for ResMut<'a, T> {
+    // the resource is moved out of the world while the system runs
+    type State = T;
+    type Item<'w> = ResMut<'w, T>;
+
+    fn take(ecs: &mut ECS) -> T {
+        match ecs.remove_resource::<T>() {
+            Some(value) => value,
+            None => panic!("resource {} is missing or borrowed mutably", type_name::<T>())
+        }
+    }
+
+    fn get<'w>(_ecs: &'w ECS, state: &'w mut T) -> ResMut<'w, T> {
+        ResMut {
+            value: state
+        }
+    }
+
+    fn restore(ecs: &mut ECS, state: T) {
+        ecs.insert_resource(state);
+    }
+}
+
+// commands recorded by running function systems, applied once all of
+// their parameters are back in the world
+struct QueuedCommands(Vec<Commands>);
+
+impl SystemParam for &mut Commands {
+    type State = Commands;
+    type Item<'w> = &'w mut Commands;
+
+    fn take(_ecs: &mut ECS) -> Commands {
+        Commands::new()
+    }
+
+    fn get<'w>(_ecs: &'w ECS, state: &'w mut Commands) -> &'w mut Commands {
+        state
+    }
+
+    fn restore(ecs: &mut ECS, state: Commands) {
+        if state.is_empty() {
+            return;
+        }
+        match ecs.get_resource_mut::<QueuedCommands>() {
+            Some(queued) => queued.0.push(state),
+            None => ecs.insert_resource(QueuedCommands(vec![state]))
+        }
+    }
+}
+
+fn apply_queued_commands(ecs: &mut ECS) {
+    if let Some(queued) = ecs.remove_resource::<QueuedCommands>() {
+        for commands in queued.0 {
+            ecs.apply_commands(commands);
+        }
+    }
+}
+
+/// A function or closure run as a `System`, created by `IntoSystem`.
+pub struct FunctionSystem<F, P> {
+    function: F,
+    params: PhantomData<fn(P)>,
+}
+
+/// Turns a function or closure whose parameters all implement
+/// `SystemParam` into a `System`, e.g.
+/// `(|mut score: ResMut<Score>, enemies: Query<Enemy>| ...).into_system()`.
+///
+/// A `&mut Commands` parameter records changes that are applied right
+/// after the system has run.
+///
+/// Running the system panics if a requested resource is missing or
+/// requested more than once while one of the requests is a `ResMut`.
+pub trait IntoSystem<P>: Sized {
+    fn into_system(self) -> FunctionSystem<Self, P>;
+}
+
+impl<F, P> IntoSystem<P> for F where FunctionSystem<F, P>: System {
+    fn into_system(self) -> FunctionSystem<F, P> {
+        FunctionSystem {
+            function: self,
+            params: PhantomData
+        }
+    }
+}
+
+macro_rules! impl_function_system {
+    ($($param:ident),+) => {
+        impl<Function, $($param: SystemParam),+> System for FunctionSystem<Function, ($($param,)+)>
+        where
+            Function: FnMut($($param),+) + FnMut($(<$param as SystemParam>::Item<'_>),+)
+        {
+            #[allow(non_snake_case)]
+            fn run(&mut self, ecs: &mut ECS) {
+                // picks the higher-ranked FnMut bound when calling
+                fn call<$($param),+>(mut function: impl FnMut($($param),+), params: ($($param,)+)) {
+                    let ($($param,)+) = params;
+                    function($($param),+)
+                }
+
+                let mut states = ($(<$param as SystemParam>::take(ecs),)+);
+                {
+                    let world: &ECS = ecs;
+                    let ($($param,)+) = &mut states;
+                    call(&mut self.function, ($(<$param as SystemParam>::get(world, $param),)+));
+                }
+                let ($($param,)+) = states;
+                $(<$param as SystemParam>::restore(ecs, $param);)+
+                apply_queued_commands(ecs);
+            }
+        }
+    };
+}
+
+impl_function_system!(A);
+impl_function_system!(A, B);
+impl_function_system!(A, B, C);
+impl_function_system!(A, B, C, D);
+impl_function_system!(A, B, C, D, E);
+impl_function_system!(A, B, C, D, E, F);
+impl_function_system!(A, B, C, D, E, F, G);
+impl_function_system!(A, B, C, D, E, F, G, H);
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ecs::Scheduler;
+
+    struct Enemy(u32);
+    struct Score(u32);
+    struct Multiplier(u32);
+
+    #[test]
+    fn test_closure_system() {
+        let mut ecs = ECS::new();
+        ecs.spawn().with_component(Enemy(1)).build();
+        ecs.spawn().with_component(Enemy(2)).build();
+        ecs.insert_resource(Score(0));
+        ecs.insert_resource(Multiplier(10));
+
+        let mut system = (|enemies: Query<Enemy>, multiplier: Res<Multiplier>, mut score: ResMut<Score>| {
+            for (_, enemy) in enemies {
+                score.0 += enemy.0 * multiplier.0;
+            }
+        }).into_system();
+        ecs.run_system(&mut system);
+        assert_eq!(ecs.get_resource::<Score>().map(|s| s.0), Some(30));
+        assert_eq!(ecs.get_resource::<Multiplier>().map(|m| m.0), Some(10));
+
+        let mut scheduler = Scheduler::new();
+        scheduler.add_system(Box::new((|mut score: ResMut<Score>| score.0 += 1).into_system()));
+        scheduler.run_all(&mut ecs);
+        assert_eq!(ecs.get_resource::<Score>().map(|s| s.0), Some(31));
+    }
+
+    #[test]
+    #[should_panic(expected = "is missing or borrowed mutably")]
+    fn test_missing_resource() {
+        let mut ecs = ECS::new();
+        let mut system = (|_score: Res<Score>| {}).into_system();
+        ecs.run_system(&mut system);
+    }
+
+    #[test]
+    #[should_panic(expected = "is missing or borrowed mutably")]
+    fn test_conflicting_resource_access() {
+        let mut ecs = ECS::new();
+        ecs.insert_resource(Score(0));
+        let mut system = (|_read: Res<Score>, _write: ResMut<Score>| {}).into_system();
+        ecs.run_system(&mut system);
+    }
+
+    #[test]
+    fn test_commands_param() {
+        let mut ecs = ECS::new();
+        ecs.spawn().with_component(Enemy(0)).build();
+        ecs.spawn().with_component(Enemy(3)).build();
+        ecs.insert_resource(Score(0));
+
+        let mut system = (|commands: &mut Commands, enemies: Query<Enemy>, mut score: ResMut<Score>| {
+            for (entity, enemy) in enemies {
+                if enemy.0 == 0 {
+                    commands.despawn(entity);
+                    score.0 += 1;
+                }
+            }
+        }).into_system();
+        ecs.run_system(&mut system);
+        assert_eq!(ecs.entity_count(), 1);
+        assert_eq!(ecs.get_resource::<Score>().map(|s| s.0), Some(1));
+        assert!(ecs.get_resource::<QueuedCommands>().is_none());
+    }
+}
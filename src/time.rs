@@ -0,0 +1,243 @@
+use std::time::Duration;
+
+use crate::ecs::{System, ECS};
+
+/// Counts down a duration that is advanced manually, e.g. once per frame
+/// with the frame time. Usually stored as a resource or component.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Timer {
+    duration: Duration,
+    elapsed: Duration,
+    repeating: bool,
+    just_finished: bool,
+    // whether a tick already reported a one-shot timer as finished; a
+    // zero duration timer is finished before its first tick
+    finish_reported: bool,
+}
+
+impl Timer {
+    /// A repeating timer starts over whenever it finishes.
+    pub fn new(duration: Duration, repeating: bool) -> Timer {
+        Timer {
+            duration,
+            elapsed: Duration::ZERO,
+            repeating,
+            just_finished: false,
+            finish_reported: false
+        }
+    }
+
+    /// Advances the timer. A repeating timer keeps the time that went past
+    /// its duration.
+    pub fn tick(&mut self, delta: Duration) {
+        self.elapsed += delta;
+        if self.repeating {
+            self.just_finished = self.elapsed >= self.duration;
+            if self.just_finished {
+                self.elapsed = match self.duration.as_nanos() {
+                    0 => Duration::ZERO,
+                    nanos => Duration::from_nanos((self.elapsed.as_nanos() % nanos) as u64)
+                };
+            }
+        } else {
+            self.elapsed = self.elapsed.min(self.duration);
+            self.just_finished = !self.finish_reported && self.finished();
+            self.finish_reported |= self.just_finished;
+        }
+    }
+
+    /// Whether the duration has passed. For a repeating timer this is the
+    /// same as `just_finished`.
+    pub fn finished(&self) -> bool {
+        if self.repeating {
+            self.just_finished
+        } else {
+            self.elapsed >= self.duration
+        }
+    }
+
+    /// Whether the last `tick` made the timer finish. For a one-shot timer
+    /// this is true for exactly one tick, even if its duration is zero.
+    pub fn just_finished(&self) -> bool {
+        self.just_finished
+    }
+
+    pub fn reset(&mut self) {
+        self.elapsed = Duration::ZERO;
+        self.just_finished = false;
+        self.finish_reported = false;
+    }
+
+    pub fn elapsed(&self) -> Duration {
+        self.elapsed
+    }
+
+    pub fn duration(&self) -> Duration {
+        self.duration
+    }
+}
+
+/// Runs its systems at a fixed rate, independent of how long frames take.
+pub struct FixedUpdate {
+    accumulator: Duration,
+    step: Duration,
+    // limits the catch-up after long frames, so slow steps cannot cause
+    // ever longer frames
+    max_steps: usize,
+    systems: Vec<Box<dyn System>>,
+}
+
+impl FixedUpdate {
+    /// Panics if `step` is zero.
+    pub fn new(step: Duration) -> FixedUpdate {
+        assert!(step > Duration::ZERO, "FixedUpdate needs a non-zero step");
+        FixedUpdate {
+            accumulator: Duration::ZERO,
+            step,
+            max_steps: 8,
+            systems: Vec::new()
+        }
+    }
+
+    /// Sets how many steps a single `advance` may run, 8 by default.
+    pub fn set_max_steps(&mut self, max_steps: usize) {
+        self.max_steps = max_steps;
+    }
+
+    pub fn add_system(&mut self, system: Box<dyn System>) {
+        self.systems.push(system);
+    }
+
+    /// Adds the frame time and runs all systems once per complete step,
+    /// returning the number of steps run. Time beyond `max_steps` steps is
+    /// dropped except for the part that does not make up a full step.
+    pub fn advance(&mut self, delta: Duration, ecs: &mut ECS) -> usize {
+        self.accumulator += delta;
+        let mut steps = 0;
+        while self.accumulator >= self.step {
+            if steps == self.max_steps {
+                self.accumulator = Duration::from_nanos((self.accumulator.as_nanos() % self.step.as_nanos()) as u64);
+                break;
+            }
+            self.accumulator -= self.step;
+            for system in self.systems.iter_mut() {
+                system.run(ecs);
+            }
+            steps += 1;
+        }
+        steps
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ms(millis: u64) -> Duration {
+        Duration::from_millis(millis)
+    }
+
+    #[test]
+    fn test_timer() {
+        let mut timer = Timer::new(ms(100), false);
+        timer.tick(ms(60));
+        assert!(!timer.finished());
+        assert!(!timer.just_finished());
+
+        timer.tick(ms(60));
+        assert!(timer.finished());
+        assert!(timer.just_finished());
+        assert_eq!(timer.elapsed(), ms(100));
+
+        timer.tick(ms(60));
+        assert!(timer.finished());
+        assert!(!timer.just_finished());
+
+        timer.reset();
+        assert!(!timer.finished());
+        assert_eq!(timer.elapsed(), Duration::ZERO);
+    }
+
+    #[test]
+    fn test_zero_duration_timer() {
+        let mut timer = Timer::new(Duration::ZERO, false);
+        assert!(timer.finished());
+        assert!(!timer.just_finished());
+
+        timer.tick(ms(1));
+        assert!(timer.just_finished());
+        timer.tick(ms(1));
+        assert!(!timer.just_finished());
+
+        timer.reset();
+        timer.tick(Duration::ZERO);
+        assert!(timer.just_finished());
+    }
+
+    #[test]
+    fn test_repeating_timer() {
+        let mut timer = Timer::new(ms(100), true);
+        timer.tick(ms(80));
+        assert!(!timer.finished());
+
+        timer.tick(ms(50));
+        assert!(timer.just_finished());
+        assert!(timer.finished());
+        assert_eq!(timer.elapsed(), ms(30));
+
+        timer.tick(ms(50));
+        assert!(!timer.just_finished());
+        assert!(!timer.finished());
+        assert_eq!(timer.elapsed(), ms(80));
+
+        // ticks longer than the duration wrap as well
+        timer.tick(ms(250));
+        assert!(timer.just_finished());
+        assert_eq!(timer.elapsed(), ms(30));
+
+        let mut zero = Timer::new(Duration::ZERO, true);
+        zero.tick(ms(1));
+        assert!(zero.just_finished());
+        assert_eq!(zero.elapsed(), Duration::ZERO);
+    }
+
+    struct CountRuns;
+
+    impl System for CountRuns {
+        fn run(&mut self, ecs: &mut ECS) {
+            *ecs.get_resource_mut::<u32>().unwrap() += 1;
+        }
+    }
+
+    #[test]
+    fn test_fixed_update() {
+        let mut ecs = ECS::new();
+        ecs.insert_resource(0u32);
+        let mut fixed = FixedUpdate::new(ms(10));
+        fixed.add_system(Box::new(CountRuns));
+
+        assert_eq!(fixed.advance(ms(20), &mut ecs), 2);
+        assert_eq!(ecs.get_resource::<u32>(), Some(&2));
+
+        // partial steps carry over
+        assert_eq!(fixed.advance(ms(6), &mut ecs), 0);
+        assert_eq!(fixed.advance(ms(6), &mut ecs), 1);
+        assert_eq!(ecs.get_resource::<u32>(), Some(&3));
+    }
+
+    #[test]
+    fn test_fixed_update_max_steps() {
+        let mut ecs = ECS::new();
+        ecs.insert_resource(0u32);
+        let mut fixed = FixedUpdate::new(ms(10));
+        fixed.add_system(Box::new(CountRuns));
+        fixed.set_max_steps(3);
+
+        assert_eq!(fixed.advance(ms(1005), &mut ecs), 3);
+        assert_eq!(ecs.get_resource::<u32>(), Some(&3));
+        // the backlog is gone, only the partial step is left
+        assert_eq!(fixed.advance(ms(4), &mut ecs), 0);
+        assert_eq!(fixed.advance(ms(1), &mut ecs), 1);
+    }
+}
@@ -0,0 +1,169 @@
+use std::mem;
+
+use crate::ecs::{Scheduler, System, ECS};
+
+/// Sets up an `ECS` with components, resources and systems in one chain,
+/// e.g. `WorldBuilder::new().register_component::<Position>().build()`.
+///
+/// The added systems end up in a `Scheduler` resource of the built world,
+/// see `ECS::run_scheduler`.
+pub struct WorldBuilder {
+    ecs: ECS,
+    scheduler: Scheduler,
+}
+
+impl WorldBuilder {
+    pub fn new() -> WorldBuilder {
+        WorldBuilder {
+            ecs: ECS::new(),
+            scheduler: Scheduler::new()
+        }
+    }
+
+    pub fn register_component<T: 'static>(&mut self) -> &mut WorldBuilder {
+        self.ecs.register_component::<T>();
+        self
+    }
+
+    pub fn insert_resource<T: 'static>(&mut self, value: T) -> &mut WorldBuilder {
+        self.ecs.insert_resource(value);
+        self
+    }
+
+    /// Systems run in the order they were added.
+    pub fn add_system(&mut self, system: impl System + 'static) -> &mut WorldBuilder {
+        self.scheduler.add_system(Box::new(system));
+        self
+    }
+
+    /// Lets the plugin set up its part of the world right away.
+    pub fn add_plugin(&mut self, plugin: impl Plugin) -> &mut WorldBuilder {
+        plugin.build(self);
+        self
+    }
+
+    /// Leaves the builder empty, so it can be reused for another world.
+    pub fn build(&mut self) -> ECS {
+        let mut ecs = mem::take(&mut self.ecs);
+        ecs.insert_resource(mem::take(&mut self.scheduler));
+        ecs
+    }
+}
+
+/// A reusable bundle of components, resources and systems, e.g. provided
+/// by another crate, see `WorldBuilder::add_plugin`.
+pub trait Plugin {
+    fn build(&self, world: &mut WorldBuilder);
+}
+
+impl Default for WorldBuilder {
+    fn default() -> WorldBuilder {
+        WorldBuilder::new()
+    }
+}
+
+impl ECS {
+    /// Runs all systems of the `Scheduler` resource once, see
+    /// `Scheduler::run_all`. Returns `false` if there is no such resource.
+    pub fn run_scheduler(&mut self) -> bool {
+        // moved out while running, so the systems get the whole world
+        let mut scheduler = match self.remove_resource::<Scheduler>() {
+            Some(scheduler) => scheduler,
+            None => return false
+        };
+        scheduler.run_all(self);
+        self.insert_resource(scheduler);
+        true
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::query::Query;
+    use crate::system::{IntoSystem, ResMut};
+
+    struct Position(i32);
+    struct Velocity(i32);
+    struct Ticks(u32);
+
+    struct Movement;
+
+    impl System for Movement {
+        fn run(&mut self, ecs: &mut ECS) {
+            for (_, position, velocity) in ecs.query2_mut::<Position, Velocity>() {
+                position.0 += velocity.0;
+            }
+        }
+    }
+
+    #[test]
+    fn test_world_builder() {
+        let mut ecs = WorldBuilder::new()
+            .register_component::<Position>()
+            .register_component::<Velocity>()
+            .insert_resource(Ticks(0))
+            .add_system(Movement)
+            .add_system((|mut ticks: ResMut<Ticks>, _positions: Query<Position>| ticks.0 += 1).into_system())
+            .build();
+
+        let infos = ecs.component_infos();
+        assert_eq!(infos.len(), 2);
+        assert!(infos.iter().all(|info| info.count == 0));
+
+        let entity = ecs.spawn()
+            .with_component(Position(0))
+            .with_component(Velocity(3))
+            .build();
+        assert!(ecs.run_scheduler());
+        assert!(ecs.run_scheduler());
+
+        assert_eq!(ecs.get_component::<Position>(entity).map(|p| p.0), Some(6));
+        assert_eq!(ecs.get_resource::<Ticks>().map(|t| t.0), Some(2));
+        assert!(!ECS::new().run_scheduler());
+    }
+
+    struct Gravity(i32);
+
+    struct Fall;
+
+    impl System for Fall {
+        fn run(&mut self, ecs: &mut ECS) {
+            let gravity = ecs.get_resource::<Gravity>().map_or(0, |gravity| gravity.0);
+            for (_, velocity) in ecs.query_mut::<Velocity>() {
+                velocity.0 += gravity;
+            }
+        }
+    }
+
+    struct PhysicsPlugin;
+
+    impl Plugin for PhysicsPlugin {
+        fn build(&self, world: &mut WorldBuilder) {
+            world.register_component::<Velocity>()
+                .insert_resource(Gravity(-1))
+                .add_system(Fall)
+                .add_system(Movement);
+        }
+    }
+
+    #[test]
+    fn test_plugin() {
+        let mut ecs = WorldBuilder::new()
+            .register_component::<Position>()
+            .add_plugin(PhysicsPlugin)
+            .build();
+        assert_eq!(ecs.component_infos().len(), 2);
+        assert_eq!(ecs.get_resource::<Gravity>().map(|g| g.0), Some(-1));
+
+        let entity = ecs.spawn()
+            .with_component(Position(10))
+            .with_component(Velocity(0))
+            .build();
+        ecs.run_scheduler();
+        ecs.run_scheduler();
+        assert_eq!(ecs.get_component::<Velocity>(entity).map(|v| v.0), Some(-2));
+        assert_eq!(ecs.get_component::<Position>(entity).map(|p| p.0), Some(7));
+    }
+}